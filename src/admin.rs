@@ -0,0 +1,184 @@
+use crate::shared::*;
+use crate::db::RocksDbStore;
+use crate::store::{OrderStore, EventBatch, COLUMN_FAMILIES};
+use crate::markets::list_markets;
+use crate::processor::set_order_value;
+use crate::snapshot::decode_value;
+use crate::migrations::{SCHEMA_VERSION, migrate};
+use crate::rebuild::rebuild_indexes;
+
+const USAGE: &str = "Usage: admin <command>
+    markets
+    order <chain_id> <adapter_id> <order_id>
+    dump <column_family>
+    lock <hashed_secret>
+    delete-order <chain_id> <adapter_id> <order_id>
+    rebuild
+    cursors";
+
+fn parse_order_key(args: &[String]) -> OrderKey {
+    if args.len() != 3 {
+        panic!("{}", USAGE);
+    }
+    OrderKey {
+        chain_id: args[0].parse().unwrap(),
+        adapter_id: args[1].parse().unwrap(),
+        order_id: vector_as_u8_16_array(&hex::decode(&args[2]).unwrap()),
+    }
+}
+
+fn describe_order_key(key: &OrderKey) -> String {
+    format!("{}/{}/{}", key.chain_id, key.adapter_id, hex::encode(key.order_id))
+}
+
+fn describe_lock_key(key: &LockKey) -> String {
+    format!("{}/{}/{}", key.chain_id, key.adapter_id, hex::encode(key.hashed_secret))
+}
+
+fn describe_key(cf: &str, key: Vec<u8>) -> String {
+    match cf {
+        "order_static" | "order_value" => describe_order_key(&OrderKey::unserialize(key)),
+        "order_list" => {
+            let key = OrderListKey::unserialize(key);
+            format!("{}/{} -> {}/{} value: {} order: {}/{}", key.sell_chain_id, hex::encode(key.sell_asset_id), key.buy_chain_id, hex::encode(key.buy_asset_id), key.value, key.sell_adapter_id, hex::encode(key.order_id))
+        },
+        "order_lock_list" => format!("{:?}", OrderLockListKey::unserialize(key)),
+        "buy_lock" | "sell_lock" | "trade" | "pending_unlock" | "buy_lock_secret" => describe_lock_key(&LockKey::unserialize(key)),
+        "trade_list" => format!("{:?}", TradeListKey::unserialize(key)),
+        "candle" => format!("{:?}", CandleKey::unserialize(key)),
+        "asset" => format!("{:?}", AssetKey::unserialize(key)),
+        "market" => format!("{:?}", MarketKey::unserialize(key)),
+        "market_price" => format!("{:?}", MarketPriceKey::unserialize(key)),
+        "htlc" => {
+            let key = HtlcKey::unserialize(key);
+            format!("{}/{}/{}:{}", key.chain_id, key.adapter_id, hex::encode(key.txid), key.vout)
+        },
+        _ => String::from_utf8_lossy(&key).to_string(),
+    }
+}
+
+fn describe_value(cf: &str, value: &[u8]) -> String {
+    match cf {
+        "order_value" => u128::from_be_bytes(vector_as_u8_16_array(&value.to_vec())).to_string(),
+        "sync_cursor" => u64::from_be_bytes(vector_as_u8_8_array(&value.to_vec())).to_string(),
+        "buy_lock_secret" => describe_lock_key(&LockKey::unserialize(value.to_vec())),
+        _ => decode_value(cf, value),
+    }
+}
+
+/// Every lock with this hashed secret, on any chain.
+pub fn find_locks(store: &dyn OrderStore, hashed_secret: [u8; 32]) -> Vec<LockKey> {
+    let mut locks: Vec<LockKey> = Vec::new();
+    for cf in ["buy_lock", "sell_lock", "trade"].iter() {
+        for (key, _value) in store.iter(*cf) {
+            let lock_key = LockKey::unserialize(key);
+            if lock_key.hashed_secret != hashed_secret { continue };
+            if locks.iter().any(|lock| lock.serialize() == lock_key.serialize()) { continue };
+            locks.push(lock_key);
+        }
+    }
+    locks
+}
+
+/// Remove an order from the order book and the market index. Its locks and trades are kept.
+pub fn delete_order(store: &dyn OrderStore, order_key: &OrderKey) -> bool {
+    if store.get_order(order_key).is_none() {
+        return false;
+    }
+    let mut batch = EventBatch::new(store);
+    set_order_value(&mut batch, order_key, 0);
+    batch.delete_order(order_key);
+    batch.flush();
+    true
+}
+
+fn print_order(store: &dyn OrderStore, order_key: &OrderKey) {
+    let order_static = match store.get_order(order_key) {
+        Some(order_static) => order_static,
+        None => {
+            println!("Order {} not found.", describe_order_key(order_key));
+            return;
+        }
+    };
+    println!("order: {}", describe_order_key(order_key));
+    println!("{}", serde_json::to_string_pretty(&order_static).unwrap());
+    println!("value: {:?}", store.get_order_value(order_key));
+    for lock in store.iter_order_locks(order_key) {
+        let lock_key = LockKey {
+            chain_id: order_key.chain_id,
+            adapter_id: order_key.adapter_id,
+            hashed_secret: lock.hashed_secret,
+        };
+        print_lock(store, &lock_key);
+    }
+}
+
+fn print_lock(store: &dyn OrderStore, lock_key: &LockKey) {
+    println!("lock: {}", describe_lock_key(lock_key));
+    println!("    buy_lock: {}", serde_json::to_string(&store.get_buy_lock(lock_key)).unwrap());
+    println!("    sell_lock: {}", serde_json::to_string(&store.get_sell_lock(lock_key)).unwrap());
+    println!("    trade: {}", serde_json::to_string(&store.get_trade(lock_key)).unwrap());
+}
+
+/// Inspect or repair the database. Everything except delete-order and rebuild opens it read-only, so it can be used while the indexer is running.
+pub fn admin(path: &str, args: &[String]) {
+    let command = match args.get(0) {
+        Some(command) => command.as_str(),
+        None => panic!("{}", USAGE),
+    };
+
+    if command == "delete-order" {
+        let order_key = parse_order_key(&args[1..]);
+        let store = RocksDbStore::open(path);
+        migrate(&store);
+        match delete_order(&store, &order_key) {
+            true => println!("Deleted order {}.", describe_order_key(&order_key)),
+            false => println!("Order {} not found.", describe_order_key(&order_key)),
+        }
+        return;
+    }
+
+    if command == "rebuild" {
+        let store = RocksDbStore::open(path);
+        migrate(&store);
+        for (cf, count) in rebuild_indexes(&store) {
+            println!("{}: {} added, {} removed, {} changed", cf, count.added, count.removed, count.changed);
+        }
+        return;
+    }
+
+    let store = RocksDbStore::open_read_only(path);
+    if store.get_schema_version() != Some(SCHEMA_VERSION) {
+        panic!("Database has schema version {:?}, this indexer uses {}. Start the indexer to migrate it.", store.get_schema_version(), SCHEMA_VERSION);
+    }
+
+    match command {
+        "markets" => {
+            for (market_key, market, best_price) in list_markets(&store) {
+                println!("{}/{} -> {}/{} orders: {} total_value: {} best_price: {:?}", market_key.sell_chain_id, hex::encode(market_key.sell_asset_id), market_key.buy_chain_id, hex::encode(market_key.buy_asset_id), market.order_count, market.total_value, best_price);
+            }
+        },
+        "order" => print_order(&store, &parse_order_key(&args[1..])),
+        "dump" => {
+            let cf = match args.get(1).and_then(|name| COLUMN_FAMILIES.iter().find(|cf| **cf == name.as_str())) {
+                Some(cf) => *cf,
+                None => panic!("Column family must be one of {:?}", COLUMN_FAMILIES),
+            };
+            for (key, value) in store.iter(cf) {
+                println!("{}\t{}", describe_key(cf, key), describe_value(cf, &value));
+            }
+        },
+        "lock" => {
+            let hashed_secret = vector_as_u8_32_array(&hex::decode(args.get(1).expect(USAGE)).unwrap());
+            for lock_key in find_locks(&store, hashed_secret) {
+                print_lock(&store, &lock_key);
+            }
+        },
+        "cursors" => {
+            for (listener, block_number) in store.list_sync_cursors() {
+                println!("{}: {}", listener, block_number);
+            }
+        },
+        _ => panic!("{}", USAGE),
+    }
+}
@@ -0,0 +1,44 @@
+use crate::shared::*;
+use crate::store::OrderStore;
+use crate::config::AssetConfig;
+
+/// Write the assets from the config file into the registry, replacing any existing entries.
+pub fn load_assets(store: &dyn OrderStore, assets: &[AssetConfig]) {
+    for asset in assets {
+        let asset_key = AssetKey {
+            chain_id: asset.chain_id,
+            adapter_id: asset.adapter_id,
+            asset_id: vector_as_u8_8_array(&hex::decode(&asset.asset_id).unwrap()),
+        };
+        put_asset(store, &asset_key, &Asset {
+            symbol: asset.symbol.clone(),
+            decimals: asset.decimals,
+        });
+    }
+}
+
+pub fn put_asset(store: &dyn OrderStore, asset_key: &AssetKey, asset: &Asset) {
+    println!("asset: {:?} {:?}", asset_key, asset);
+    store.put_asset(asset_key, asset);
+}
+
+pub fn get_asset(store: &dyn OrderStore, chain_id: u32, adapter_id: u32, asset_id: [u8; 8]) -> Option<Asset> {
+    store.get_asset(&AssetKey {
+        chain_id: chain_id,
+        adapter_id: adapter_id,
+        asset_id: asset_id,
+    })
+}
+
+/// An asset on a chain with any adapter. Markets are not specific to an adapter.
+pub fn find_asset(store: &dyn OrderStore, chain_id: u32, asset_id: [u8; 8]) -> Option<Asset> {
+    list_assets(store).into_iter()
+        .find(|(asset_key, _asset)| asset_key.chain_id == chain_id && asset_key.asset_id == asset_id)
+        .map(|(_asset_key, asset)| asset)
+}
+
+pub fn list_assets(store: &dyn OrderStore) -> Vec<(AssetKey, Asset)> {
+    store.iter("asset")
+        .map(|(key, value)| (AssetKey::unserialize(key), bincode::deserialize(&value).unwrap()))
+        .collect()
+}
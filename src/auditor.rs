@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::Sender;
+use tokio::time::sleep;
+
+use crate::shared::*;
+use crate::store::{OrderStore, EventBatch};
+use crate::processor::set_order_value;
+use crate::config::{AuditConfig, SubstrateConfig};
+use crate::substrate::substrate_audit;
+use crate::ethereum::ethereum_audit;
+use crate::arbitrum::arbitrum_audit;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Drift {
+    OrderValue {
+        order_id: [u8; 16],
+        database: u128,
+        chain: u128,
+    },
+    SellLock {
+        hashed_secret: [u8; 32],
+        database: String,
+        chain: u128,
+    },
+    BuyLock {
+        hashed_secret: [u8; 32],
+        database: String,
+        chain: u128,
+    },
+}
+
+/// A sell lock on the audited chain, with the buy lock it was made against.
+pub struct AuditedSellLock {
+    pub lock_key: LockKey,
+    pub sell_lock: SellLock,
+    pub buy_lock: BuyLock,
+}
+
+/// A buy lock on the audited chain, for an order on another chain.
+pub struct AuditedBuyLock {
+    pub lock_key: LockKey,
+    pub buy_lock: BuyLock,
+    pub order_static: OrderStatic,
+}
+
+/// Everything in the database that belongs to one chain and adapter. Each chain reads the same state from its own
+/// storage, so no chain audits records another chain indexed.
+pub struct AuditScope {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub orders: Vec<(OrderKey, u128)>,
+    pub sell_locks: Vec<AuditedSellLock>,
+    pub buy_locks: Vec<AuditedBuyLock>,
+}
+
+/// State read from a chain for an AuditScope. Locks hold their value on chain, 0 once they are no longer held.
+#[derive(Default)]
+pub struct ChainState {
+    pub order_values: BTreeMap<[u8; 16], u128>,
+    pub sell_locks: BTreeMap<[u8; 32], u128>,
+    pub buy_locks: BTreeMap<[u8; 32], u128>,
+}
+
+impl AuditScope {
+    /// The scope of a chain together with the block its listener has indexed up to, read under the commit lock so both
+    /// reflect the same batches. Reading the chain at that block keeps events that have not been indexed yet from being
+    /// reported as drift. None when the listener has not indexed a block yet.
+    pub fn at_sync_cursor(store: &dyn OrderStore, listener: &str, chain_id: u32, adapter_id: u32) -> Option<(AuditScope, u64)> {
+        let _guard = store.commit_lock().lock().unwrap();
+        let block_number = store.get_sync_cursor(listener)?;
+        Some((AuditScope::new(store, chain_id, adapter_id), block_number))
+    }
+
+    pub fn new(store: &dyn OrderStore, chain_id: u32, adapter_id: u32) -> AuditScope {
+        // Orders selling on this chain.
+        let start_key = OrderKey {
+            chain_id: chain_id,
+            adapter_id: adapter_id,
+            order_id: <[u8; 16]>::default(),
+        };
+        let orders = store.iter_from("order_value", &start_key.serialize())
+            .map(|(key, value)| (OrderKey::unserialize(key), u128::from_be_bytes(vector_as_u8_16_array(&value))))
+            .take_while(|(order_key, _value)| order_key.chain_id == chain_id && order_key.adapter_id == adapter_id)
+            .collect();
+
+        // Sell locks on this chain.
+        let start_key = LockKey {
+            chain_id: chain_id,
+            adapter_id: adapter_id,
+            hashed_secret: <[u8; 32]>::default(),
+        };
+        let sell_locks = store.iter_from("sell_lock", &start_key.serialize())
+            .map(|(key, value)| (LockKey::unserialize(key), value))
+            .take_while(|(lock_key, _value)| lock_key.chain_id == chain_id && lock_key.adapter_id == adapter_id)
+            .filter_map(|(lock_key, value)| {
+                let buy_lock = store.get_buy_lock(&lock_key)?;
+                Some(AuditedSellLock {
+                    lock_key: lock_key,
+                    sell_lock: bincode::deserialize(&value).unwrap(),
+                    buy_lock: buy_lock,
+                })
+            })
+            .collect();
+
+        // Buy locks on this chain, i.e. locks for orders that buy this chain's asset.
+        let buy_locks = store.iter("buy_lock")
+            .filter_map(|(key, value)| {
+                let lock_key = LockKey::unserialize(key);
+                let buy_lock: BuyLock = bincode::deserialize(&value).unwrap();
+                let order_static = store.get_order(&OrderKey {
+                    chain_id: lock_key.chain_id,
+                    adapter_id: lock_key.adapter_id,
+                    order_id: buy_lock.order_id,
+                })?;
+                if order_static.chain_id != chain_id || order_static.adapter_id != adapter_id { return None };
+                Some(AuditedBuyLock {
+                    lock_key: lock_key,
+                    buy_lock: buy_lock,
+                    order_static: order_static,
+                })
+            })
+            .collect();
+
+        AuditScope {
+            chain_id: chain_id,
+            adapter_id: adapter_id,
+            orders: orders,
+            sell_locks: sell_locks,
+            buy_locks: buy_locks,
+        }
+    }
+
+    /// Compare the database with the chain. Locks missing from `chain` are not compared, for chains whose lock state
+    /// cannot be read.
+    pub fn find_drift(&self, chain: &ChainState) -> Vec<Drift> {
+        let mut drifts: Vec<Drift> = Vec::new();
+        for (order_key, database) in self.orders.iter() {
+            let chain = chain.order_values.get(&order_key.order_id).cloned().unwrap_or(0);
+            if *database != chain {
+                drifts.push(Drift::OrderValue {
+                    order_id: order_key.order_id,
+                    database: *database,
+                    chain: chain,
+                });
+            }
+        }
+        for lock in self.sell_locks.iter() {
+            let chain = match chain.sell_locks.get(&lock.lock_key.hashed_secret) {
+                Some(chain) => *chain,
+                None => continue,
+            };
+            if lock_drifted(&lock.sell_lock.state, chain) {
+                drifts.push(Drift::SellLock {
+                    hashed_secret: lock.lock_key.hashed_secret,
+                    database: lock.sell_lock.state.to_string(),
+                    chain: chain,
+                });
+            }
+        }
+        for lock in self.buy_locks.iter() {
+            let chain = match chain.buy_locks.get(&lock.lock_key.hashed_secret) {
+                Some(chain) => *chain,
+                None => continue,
+            };
+            if lock_drifted(&lock.buy_lock.state, chain) {
+                drifts.push(Drift::BuyLock {
+                    hashed_secret: lock.lock_key.hashed_secret,
+                    database: lock.buy_lock.state.to_string(),
+                    chain: chain,
+                });
+            }
+        }
+        drifts
+    }
+}
+
+// A lock is held on chain while its value is non-zero.
+fn lock_drifted(state: &LockState, chain: u128) -> bool {
+    match state {
+        LockState::Locked | LockState::Unverified => chain == 0,
+        _ => chain != 0,
+    }
+}
+
+/// Log every drift and, when repairing, overwrite drifted order values with the values on chain. Lock drift is only
+/// reported: a lock that is no longer held on chain could have been unlocked or timed out. An order whose value has
+/// been changed by the listener since the audit read it is not repaired.
+pub fn report_drift(store: &dyn OrderStore, tx: &Sender<RequestMessage>, name: &str, scope: &AuditScope, drifts: Vec<Drift>, repair: bool) {
+    for drift in drifts {
+        println!("{} audit: {:?}", name, drift);
+        if !repair { continue };
+        if let Drift::OrderValue { order_id, database, chain } = drift {
+            let order_key = OrderKey {
+                chain_id: scope.chain_id,
+                adapter_id: scope.adapter_id,
+                order_id: order_id,
+            };
+            let mut batch = EventBatch::new(store);
+            set_order_value(&mut batch, &order_key, chain);
+            batch.notify(RequestMessage::GetOrder { sell_chain_id: scope.chain_id, sell_adapter_id: scope.adapter_id, order_id: hex::encode(order_id) });
+            if !batch.commit_if(tx, |store| store.get_order_value(&order_key).unwrap_or(0) == database) {
+                println!("{} audit: order {} changed since it was audited, not repaired.", name, hex::encode(order_id));
+            }
+        }
+    }
+}
+
+/// Periodically compare the database with every chain, logging drift and optionally repairing it. A chain that cannot
+/// be read is logged and audited again next time.
+pub async fn auditor_run(db: Arc<dyn OrderStore>, tx: Sender<RequestMessage>, config: AuditConfig, substrate: Vec<SubstrateConfig>) {
+    loop {
+        println!("Audit started.");
+        for substrate_config in substrate.iter() {
+            if let Err(err) = substrate_audit(db.clone(), tx.clone(), substrate_config, config.repair).await {
+                eprintln!("{} audit failed: {}", substrate_config.name, err);
+            }
+        }
+        if let Err(err) = ethereum_audit(db.clone(), tx.clone(), config.repair).await {
+            eprintln!("Ethereum audit failed: {}", err);
+        }
+        if let Err(err) = arbitrum_audit(db.clone(), tx.clone(), config.repair).await {
+            eprintln!("Arbitrum audit failed: {}", err);
+        }
+        println!("Audit finished.");
+        sleep(Duration::from_secs(config.interval)).await;
+    }
+}
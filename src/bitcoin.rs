@@ -0,0 +1,292 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::bitcoin::{Block, Script, Transaction};
+use bitcoincore_rpc::bitcoin::blockdata::opcodes::all::*;
+use bitcoincore_rpc::bitcoin::blockdata::script::{read_scriptint, Builder, Instruction};
+use bitcoincore_rpc::bitcoin::hashes::{hash160, Hash};
+use sp_io::hashing::{keccak_256, sha2_256};
+use tokio::sync::broadcast::Sender;
+
+use crate::shared::*;
+use crate::config::BitcoinConfig;
+use crate::processor::{EventMeta, SwapEvent, process_event};
+use crate::store::{OrderStore, EventBatch};
+
+// Bitcoin scripts cannot hash with keccak, so HTLCs lock with SHA-256. The funding transaction commits to the keccak hash
+// of the same secret in an OP_RETURN output, so the buy lock can be matched with the sell lock on the other chain:
+//
+//   orderId (16 bytes), hashedSecret (keccak, 32 bytes), foreignAddress (32 bytes)
+//
+// The HTLC itself is the only P2WSH output of the funding transaction, with the witness script:
+//
+//   OP_IF
+//       OP_SHA256 <sha256 hashed secret> OP_EQUALVERIFY <seller pubkey> OP_CHECKSIG
+//   OP_ELSE
+//       <timeout> OP_CHECKLOCKTIMEVERIFY OP_DROP <buyer pubkey> OP_CHECKSIG
+//   OP_ENDIF
+//
+// The script is only revealed when the HTLC is spent: a redeem reveals the secret, a refund happens after the timeout.
+// Until then any P2WSH output could be committed to, so buy locks stay unverified until the HTLC is spent with the
+// script above.
+
+const FUNDING_DATA_LEN: usize = 80;
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Htlc {
+    pub sha256_hashed_secret: [u8; 32],
+    pub seller: Vec<u8>,
+    pub timeout: i64,
+    pub buyer: Vec<u8>,
+}
+
+/// Witness script of an HTLC.
+pub fn htlc_script(htlc: &Htlc) -> Script {
+    Builder::new()
+        .push_opcode(OP_IF)
+        .push_opcode(OP_SHA256)
+        .push_slice(&htlc.sha256_hashed_secret)
+        .push_opcode(OP_EQUALVERIFY)
+        .push_slice(&htlc.seller)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ELSE)
+        .push_int(htlc.timeout)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_slice(&htlc.buyer)
+        .push_opcode(OP_CHECKSIG)
+        .push_opcode(OP_ENDIF)
+        .into_script()
+}
+
+/// Parse a witness script, returning None unless it is an HTLC.
+pub fn parse_htlc_script(script: &Script) -> Option<Htlc> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    match instructions.as_slice() {
+        [
+            Instruction::Op(OP_IF),
+            Instruction::Op(OP_SHA256),
+            Instruction::PushBytes(hashed_secret),
+            Instruction::Op(OP_EQUALVERIFY),
+            Instruction::PushBytes(seller),
+            Instruction::Op(OP_CHECKSIG),
+            Instruction::Op(OP_ELSE),
+            Instruction::PushBytes(timeout),
+            Instruction::Op(OP_CLTV),
+            Instruction::Op(OP_DROP),
+            Instruction::PushBytes(buyer),
+            Instruction::Op(OP_CHECKSIG),
+            Instruction::Op(OP_ENDIF),
+        ] if hashed_secret.len() == 32 => Some(Htlc {
+            sha256_hashed_secret: vector_as_u8_32_array(&hashed_secret.to_vec()),
+            seller: seller.to_vec(),
+            timeout: read_scriptint(timeout).ok()?,
+            buyer: buyer.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+/// Account of a public key in an HTLC: its HASH160, as in P2WPKH addresses, left padded like an EVM address.
+pub fn pubkey_account(pubkey: &[u8]) -> [u8; 32] {
+    let mut account = [0u8; 32];
+    account[12..].copy_from_slice(&hash160::Hash::hash(pubkey).into_inner());
+    account
+}
+
+/// OP_RETURN output committing to the order and keccak hashed secret of an HTLC.
+pub fn funding_data_script(order_id: &[u8; 16], hashed_secret: &[u8; 32], foreign_address: &[u8; 32]) -> Script {
+    let data = [&order_id[..], &hashed_secret[..], &foreign_address[..]].concat();
+    Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(&data)
+        .into_script()
+}
+
+fn funding_data(script: &Script) -> Option<Vec<u8>> {
+    if !script.is_op_return() { return None };
+    match script.instructions().nth(1) {
+        Some(Ok(Instruction::PushBytes(data))) if data.len() == FUNDING_DATA_LEN => Some(data.to_vec()),
+        _ => None,
+    }
+}
+
+/// Watches HTLCs on one Bitcoin family chain.
+pub struct BitcoinAdapter {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub counterpart_chain_id: u32,
+    pub counterpart_adapter_id: u32,
+}
+
+impl BitcoinAdapter {
+    pub fn new(config: &BitcoinConfig) -> BitcoinAdapter {
+        BitcoinAdapter {
+            chain_id: config.chain_id,
+            adapter_id: config.adapter_id,
+            counterpart_chain_id: config.counterpart_chain_id,
+            counterpart_adapter_id: config.counterpart_adapter_id,
+        }
+    }
+
+    /// The buy lock an HTLC funding transaction makes, with the output that holds it.
+    fn funding_event(&self, tx: &Transaction) -> Option<(HtlcKey, LockKey, SwapEvent)> {
+        let data = tx.output.iter().find_map(|output| funding_data(&output.script_pubkey))?;
+        // A second P2WSH output could be change, and there is no telling which one is the HTLC.
+        let mut htlc_outputs = tx.output.iter().enumerate().filter(|(_vout, output)| output.script_pubkey.is_v0_p2wsh());
+        let (vout, output) = htlc_outputs.next()?;
+        if htlc_outputs.next().is_some() {
+            println!("Bitcoin: funding transaction with more than one P2WSH output ignored: {:?}", tx.txid());
+            return None;
+        }
+        let order_id = vector_as_u8_16_array(&data[0..16].to_vec());
+        let hashed_secret = vector_as_u8_32_array(&data[16..48].to_vec());
+        let htlc_key = HtlcKey {
+            chain_id: self.chain_id,
+            adapter_id: self.adapter_id,
+            txid: tx.txid().into_inner(),
+            vout: vout as u32,
+        };
+        let lock_key = LockKey {
+            chain_id: self.counterpart_chain_id,
+            adapter_id: self.counterpart_adapter_id,
+            hashed_secret: hashed_secret,
+        };
+
+        // The buyer and timeout are in the witness script, which is only revealed when the HTLC is spent.
+        Some((htlc_key, lock_key, SwapEvent::LockBuy {
+            chain_id: self.counterpart_chain_id,
+            adapter_id: self.counterpart_adapter_id,
+            order_id: order_id,
+            buyer: <[u8; 32]>::default(),
+            seller: <[u8; 32]>::default(),
+            hashed_secret: hashed_secret,
+            timeout: 0,
+            value: output.value as u128,
+            foreign_address: vector_as_u8_32_array(&data[48..80].to_vec()),
+            verified: false,
+        }))
+    }
+
+    fn spend_events(&self, store: &dyn OrderStore, tx: &Transaction) -> Vec<SwapEvent> {
+        let mut events = Vec::new();
+        for input in tx.input.iter() {
+            let lock_key = match store.get_htlc(&HtlcKey {
+                chain_id: self.chain_id,
+                adapter_id: self.adapter_id,
+                txid: input.previous_output.txid.into_inner(),
+                vout: input.previous_output.vout,
+            }) {
+                Some(lock_key) => lock_key,
+                None => continue,
+            };
+            let invalid = SwapEvent::InvalidBuy {
+                chain_id: lock_key.chain_id,
+                adapter_id: lock_key.adapter_id,
+                hashed_secret: lock_key.hashed_secret,
+            };
+            // The witness script hashes to the output's P2WSH program, so this is the script the output was locked with.
+            let htlc = match input.witness.last().and_then(|script| parse_htlc_script(&Script::from(script.clone()))) {
+                Some(htlc) => htlc,
+                None => {
+                    println!("Bitcoin: HTLC spent with an unknown witness script: {:?}", tx.txid());
+                    events.push(invalid);
+                    continue;
+                }
+            };
+            let buyer = pubkey_account(&htlc.buyer);
+            let verify = SwapEvent::VerifyBuy {
+                chain_id: lock_key.chain_id,
+                adapter_id: lock_key.adapter_id,
+                hashed_secret: lock_key.hashed_secret,
+                buyer: buyer,
+                seller: pubkey_account(&htlc.seller),
+                timeout: std::cmp::max(htlc.timeout, 0) as u128,
+            };
+
+            // Redeem: <signature> <secret> 1 <script>, refund: <signature> <> <script>
+            match input.witness.len() {
+                4 => {
+                    let secret = &input.witness[1];
+                    if secret.len() != 32 || sha2_256(secret) != htlc.sha256_hashed_secret || keccak_256(secret) != lock_key.hashed_secret {
+                        // The funding transaction committed to a keccak hash of a different secret.
+                        println!("Bitcoin: HTLC secret does not match hashed secret {:?}", hex::encode(lock_key.hashed_secret));
+                        events.push(invalid);
+                        continue;
+                    }
+                    events.push(verify);
+                    events.push(SwapEvent::UnlockBuy {
+                        buyer: buyer,
+                        hashed_secret: lock_key.hashed_secret,
+                    });
+                },
+                3 => {
+                    events.push(verify);
+                    events.push(SwapEvent::TimeoutBuy {
+                        buyer: buyer,
+                        hashed_secret: lock_key.hashed_secret,
+                    });
+                },
+                _ => println!("Bitcoin: HTLC spent with an unexpected witness: {:?}", tx.txid()),
+            }
+        }
+        events
+    }
+
+    /// Process every transaction in a block, in order, so an HTLC can be funded and spent in the same block. HTLC
+    /// outputs are recorded in the batch as they are funded.
+    pub fn process_block(&self, batch: &mut EventBatch, block: &Block, block_number: u64) {
+        for tx in block.txdata.iter() {
+            let meta = EventMeta {
+                chain_id: self.chain_id,
+                adapter_id: self.adapter_id,
+                block_number: block_number,
+                block_time: block.header.time as u64 * 1000,
+                tx_hash: tx.txid().into_inner(),
+            };
+            let mut events = self.spend_events(&*batch, tx);
+            if let Some((htlc_key, lock_key, event)) = self.funding_event(tx) {
+                batch.put_htlc(&htlc_key, &lock_key);
+                events.push(event);
+            }
+            for event in events {
+                process_event(batch, &meta, &event);
+            }
+        }
+    }
+}
+
+/// Process one block. HTLC outputs, lock state and the sync cursor are committed together.
+pub fn sync_block(rpc: &Client, db: &dyn OrderStore, tx: &Sender<RequestMessage>, adapter: &BitcoinAdapter, name: &str, block_number: u64) {
+    let hash = rpc.get_block_hash(block_number).unwrap();
+    let block = rpc.get_block(&hash).unwrap();
+    let mut batch = EventBatch::new(db);
+    adapter.process_block(&mut batch, &block, block_number);
+    batch.set_sync_cursor(name, block_number);
+    batch.commit(tx);
+}
+
+pub async fn bitcoin_listen(db: Arc<dyn OrderStore>, tx: Sender<RequestMessage>, config: BitcoinConfig) {
+    // The RPC client is blocking.
+    tokio::task::spawn_blocking(move || {
+        let rpc = Client::new(&config.url, Auth::UserPass(config.user.clone(), config.password.clone())).unwrap();
+        let adapter = BitcoinAdapter::new(&config);
+        println!("Connected to {}.", config.name);
+
+        let mut next = match db.get_sync_cursor(&config.name) {
+            Some(block_number) => block_number + 1,
+            None => config.start_block,
+        };
+        loop {
+            let height = rpc.get_block_count().unwrap();
+            while next + config.confirmations <= height + 1 {
+                sync_block(&rpc, &*db, &tx, &adapter, &config.name, next);
+                next += 1;
+            }
+            thread::sleep(Duration::from_secs(config.poll_interval));
+        }
+    }).await.unwrap();
+}
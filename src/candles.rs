@@ -0,0 +1,91 @@
+use crate::shared::*;
+use crate::store::{OrderStore, EventBatch};
+
+/// Fold a completed trade into the candles for every interval and notify subscribers.
+pub fn update_candles(batch: &mut EventBatch, trade: &Trade) {
+    let timestamp = trade.timestamp();
+
+    for interval in CandleInterval::ALL.iter() {
+        let candle_key = CandleKey {
+            sell_chain_id: trade.sell_chain_id,
+            sell_asset_id: trade.sell_asset_id,
+            buy_chain_id: trade.buy_chain_id,
+            buy_asset_id: trade.buy_asset_id,
+            interval: interval.seconds(),
+            start: interval.start(timestamp),
+        };
+
+        let candle = match batch.get_candle(&candle_key) {
+            Some(mut candle) => {
+                // Trades can complete out of order, so open and close follow the trade timestamps.
+                if timestamp < candle.open_time {
+                    candle.open = trade.price;
+                    candle.open_time = timestamp;
+                }
+                if timestamp >= candle.close_time {
+                    candle.close = trade.price;
+                    candle.close_time = timestamp;
+                }
+                candle.high = candle.high.max(trade.price);
+                candle.low = candle.low.min(trade.price);
+                candle.volume += trade.value;
+                candle.trades += 1;
+                candle
+            },
+            None => Candle {
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                volume: trade.value,
+                trades: 1,
+                open_time: timestamp,
+                close_time: timestamp,
+            },
+        };
+
+        println!("candle: {:?} {:?}", candle_key, candle);
+        batch.put_candle(&candle_key, &candle);
+
+        batch.notify(RequestMessage::GetCandles {
+            sell_chain_id: trade.sell_chain_id,
+            sell_asset_id: hex::encode(trade.sell_asset_id),
+            buy_chain_id: trade.buy_chain_id,
+            buy_asset_id: hex::encode(trade.buy_asset_id),
+            interval: *interval,
+            from: Some(candle_key.start),
+            to: Some(candle_key.start),
+            limit: Some(1),
+        });
+    }
+}
+
+/// Candles in a market for one interval, oldest first, with start times between `from` and `to` inclusive.
+pub fn get_candles(store: &dyn OrderStore, sell_chain_id: u32, sell_asset_id: [u8; 8], buy_chain_id: u32, buy_asset_id: [u8; 8], interval: CandleInterval, from: u64, to: u64, limit: usize) -> Vec<(u64, Candle)> {
+    let start_key = CandleKey {
+        sell_chain_id: sell_chain_id,
+        sell_asset_id: sell_asset_id,
+        buy_chain_id: buy_chain_id,
+        buy_asset_id: buy_asset_id,
+        interval: interval.seconds(),
+        start: interval.start(from),
+    };
+
+    let iterator = store.iter_from("candle", &start_key.serialize());
+    let mut candles: Vec<(u64, Candle)> = Vec::new();
+
+    for (key, value) in iterator {
+        if candles.len() >= limit { break };
+        let candle_key = CandleKey::unserialize(key);
+        if candle_key.sell_chain_id != sell_chain_id ||
+            candle_key.sell_asset_id != sell_asset_id ||
+            candle_key.buy_chain_id != buy_chain_id ||
+            candle_key.buy_asset_id != buy_asset_id ||
+            candle_key.interval != interval.seconds() ||
+            candle_key.start > to { break };
+
+        candles.push((candle_key.start, bincode::deserialize(&value).unwrap()));
+    }
+
+    candles
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use serde::{de::Error, Deserialize, Deserializer};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AssetConfig {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub asset_id: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    // Seconds between audits.
+    pub interval: u64,
+    // Overwrite drifted order values with the values on chain.
+    pub repair: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> AuditConfig {
+        AuditConfig {
+            enabled: false,
+            interval: 3600,
+            repair: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    // JSON lines file the raw events are written to. Overwritten on startup.
+    pub path: String,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> RecorderConfig {
+        RecorderConfig {
+            enabled: false,
+            path: "recording.jsonl".to_string(),
+        }
+    }
+}
+
+/// Limits applied to each websocket client.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Quota {
+    // Open connections from one IP address.
+    pub max_connections_per_ip: usize,
+    // Sustained requests per second on one connection.
+    pub requests_per_second: f64,
+    // Requests that can be sent at once before the rate limit applies.
+    pub burst: f64,
+}
+
+/// An API key for our own services, with a higher quota.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub quota: Quota,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    // PEM certificate chain and private key.
+    pub cert_path: String,
+    pub key_path: String,
+    // Seconds between checks for a renewed certificate.
+    #[serde(deserialize_with = "positive_interval")]
+    pub reload_interval: u64,
+}
+
+// An interval of 0 would poll in a busy loop.
+fn positive_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match u64::deserialize(deserializer)? {
+        0 => Err(D::Error::custom("interval must be at least 1 second")),
+        interval => Ok(interval),
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            enabled: false,
+            cert_path: "cert.pem".to_string(),
+            key_path: "key.pem".to_string(),
+            reload_interval: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebsocketConfig {
+    pub address: String,
+    // Largest frame and message a client can send, in bytes.
+    pub max_frame_size: usize,
+    // Origins allowed to connect. Any origin is allowed when empty.
+    pub allowed_origins: Vec<String>,
+    // Quota of clients without an API key.
+    pub quota: Quota,
+    pub api_keys: Vec<ApiKeyConfig>,
+    // Messages each connection can fall behind by before it is sent a resync. Every order book delta is one message.
+    pub broadcast_capacity: usize,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> WebsocketConfig {
+        WebsocketConfig {
+            address: "127.0.0.1:8080".to_string(),
+            max_frame_size: 64 * 1024,
+            allowed_origins: Vec::new(),
+            quota: Quota {
+                max_connections_per_ip: 8,
+                requests_per_second: 10.0,
+                burst: 20.0,
+            },
+            api_keys: Vec::new(),
+            broadcast_capacity: 4096,
+        }
+    }
+}
+
+/// A Substrate chain with the AtomicSwap pallet.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubstrateConfig {
+    // Name of the listener, used for its sync cursor.
+    pub name: String,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub url: String,
+}
+
+fn default_substrate() -> Vec<SubstrateConfig> {
+    vec![SubstrateConfig {
+        name: "acuity".to_string(),
+        chain_id: 76,
+        adapter_id: 0,
+        url: "ws://127.0.0.1:9946".to_string(),
+    }]
+}
+
+/// A Bitcoin family chain watched through bitcoind RPC.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BitcoinConfig {
+    // Name of the listener, used for its sync cursor.
+    pub name: String,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    // Chain of the orders that are bought with HTLCs on this chain.
+    pub counterpart_chain_id: u32,
+    pub counterpart_adapter_id: u32,
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    // Block to start from when there is no sync cursor.
+    #[serde(default)]
+    pub start_block: u64,
+    // Blocks are only processed once they have this many confirmations.
+    #[serde(default = "default_confirmations")]
+    pub confirmations: u64,
+    // Seconds between polls for new blocks.
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn default_confirmations() -> u64 {
+    1
+}
+
+fn default_poll_interval() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub assets: Vec<AssetConfig>,
+    // Allow websocket clients to add assets to the registry.
+    pub allow_asset_registration: bool,
+    pub audit: AuditConfig,
+    pub recorder: RecorderConfig,
+    pub websocket: WebsocketConfig,
+    // TLS for every server.
+    pub tls: TlsConfig,
+    // Defaults to Acuity on a local node.
+    pub substrate: Vec<SubstrateConfig>,
+    pub bitcoin: Vec<BitcoinConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            assets: Vec::new(),
+            allow_asset_registration: false,
+            audit: AuditConfig::default(),
+            recorder: RecorderConfig::default(),
+            websocket: WebsocketConfig::default(),
+            tls: TlsConfig::default(),
+            substrate: default_substrate(),
+            bitcoin: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Config {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).unwrap(),
+            Err(_) => {
+                println!("Config file {} not found, using defaults.", path);
+                Config::default()
+            },
+        }
+    }
+}
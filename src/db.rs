@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+use rocksdb::{DB, ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch};
+
+use crate::store::{OrderStore, CfWrite, COLUMN_FAMILIES};
+
+pub fn open_db(path: &str) -> DB {
+    let mut db_opts = Options::default();
+    db_opts.create_if_missing(true);
+    db_opts.create_missing_column_families(true);
+    let cfs = COLUMN_FAMILIES.iter()
+        .map(|name| ColumnFamilyDescriptor::new(*name, Options::default()))
+        .collect::<Vec<_>>();
+    DB::open_cf_descriptors(&db_opts, path, cfs).unwrap()
+}
+
+/// Open without taking the lock, so a running indexer's database can be inspected. Writes fail.
+pub fn open_db_read_only(path: &str) -> DB {
+    DB::open_cf_for_read_only(&Options::default(), path, COLUMN_FAMILIES.iter(), false).unwrap()
+}
+
+/// OrderStore backed by RocksDB, one column family per table.
+pub struct RocksDbStore {
+    db: DB,
+    commit_lock: Mutex<()>,
+}
+
+impl RocksDbStore {
+    pub fn new(db: DB) -> RocksDbStore {
+        RocksDbStore {
+            db: db,
+            commit_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn open(path: &str) -> RocksDbStore {
+        RocksDbStore::new(open_db(path))
+    }
+
+    pub fn open_read_only(path: &str) -> RocksDbStore {
+        RocksDbStore::new(open_db_read_only(path))
+    }
+}
+
+impl OrderStore for RocksDbStore {
+    fn get(&self, cf: &'static str, key: &[u8]) -> Option<Vec<u8>> {
+        self.db.get_cf(&self.db.cf_handle(cf).unwrap(), key).unwrap()
+    }
+
+    fn put(&self, cf: &'static str, key: &[u8], value: &[u8]) {
+        self.db.put_cf(&self.db.cf_handle(cf).unwrap(), key, value).unwrap();
+    }
+
+    fn delete(&self, cf: &'static str, key: &[u8]) {
+        self.db.delete_cf(&self.db.cf_handle(cf).unwrap(), key).unwrap();
+    }
+
+    fn iter_from<'a>(&'a self, cf: &'static str, from: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        let iterator = self.db.iterator_cf(&self.db.cf_handle(cf).unwrap(), IteratorMode::From(from, Direction::Forward));
+        Box::new(iterator.map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+
+    fn write(&self, writes: Vec<CfWrite>) {
+        let mut batch = WriteBatch::default();
+        for (cf, key, value) in writes {
+            match value {
+                Some(value) => batch.put_cf(&self.db.cf_handle(cf).unwrap(), key, value),
+                None => batch.delete_cf(&self.db.cf_handle(cf).unwrap(), key),
+            }
+        }
+        self.db.write(batch).unwrap();
+    }
+
+    fn commit_lock(&self) -> &Mutex<()> {
+        &self.commit_lock
+    }
+}
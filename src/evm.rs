@@ -0,0 +1,329 @@
+use web3::contract::Contract;
+use web3::ethabi::{Contract as Abi, Error, Token};
+use web3::transports::WebSocket;
+use web3::types::{Address, BlockId, BlockNumber, Bytes, CallRequest, Filter, FilterBuilder, Log, U256};
+use web3::Web3;
+use web3::futures::StreamExt;
+use sp_io::hashing::keccak_256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::Sender;
+use tokio::time::sleep;
+
+use crate::shared::*;
+use crate::auditor::{AuditScope, ChainState};
+use crate::processor::{EventMeta, SwapEvent, process_event};
+use crate::evm_events::{EvmEvent, parse_log};
+use crate::recorder::{Recorder, RecordedEvent, RawEvent, ChainReads};
+use crate::store::{OrderStore, EventBatch};
+
+/// Most blocks one eth_getLogs query covers while backfilling.
+const LOG_RANGE: u64 = 2000;
+
+/// Call a view function that returns a single uint256, at the latest block if none is given. Overloads are told apart by their number of parameters.
+pub async fn call_view(web3: &Web3<WebSocket>, contract: &Contract<WebSocket>, name: &str, params: Vec<Token>, block: Option<BlockId>) -> Result<U256, String> {
+    let function = contract.abi().functions_by_name(name)
+        .map_err(|err| format!("{}: {}", name, err))?
+        .iter()
+        .find(|function| function.inputs.len() == params.len())
+        .ok_or(format!("{}: no overload with {} parameters", name, params.len()))?;
+    let request = CallRequest {
+        to: Some(contract.address()),
+        data: Some(Bytes(function.encode_input(&params).map_err(|err| format!("{}: {}", name, err))?)),
+        ..Default::default()
+    };
+    let result = web3.eth().call(request, block).await.map_err(|err| format!("{} call failed: {}", name, err))?;
+    let output = function.decode_output(&result.0).map_err(|err| format!("{}: invalid output: {}", name, err))?;
+    output.into_iter().next()
+        .and_then(|token| token.into_uint())
+        .ok_or(format!("{}: output is not a uint256", name))
+}
+
+/// The 20 byte address stored in the last 20 bytes of a 32 byte word.
+fn address_token(word: &[u8; 32]) -> Token {
+    Token::Address(Address::from_slice(&word[12..]))
+}
+
+/// Decodes the logs of an EVM chain's sell and buy contracts into SwapEvents.
+pub struct EvmDecoder {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub sell_address: Address,
+    pub buy_address: Address,
+    sell_abi: Abi,
+    buy_abi: Abi,
+}
+
+impl EvmDecoder {
+    pub fn new(chain_id: u32, adapter_id: u32, sell_addr: Address, buy_addr: Address) -> EvmDecoder {
+        EvmDecoder {
+            chain_id: chain_id,
+            adapter_id: adapter_id,
+            sell_address: sell_addr,
+            buy_address: buy_addr,
+            sell_abi: Abi::load(&include_bytes!("AcuityAtomicSwapSell.abi")[..]).unwrap(),
+            buy_abi: Abi::load(&include_bytes!("AcuityAtomicSwapBuy.abi")[..]).unwrap(),
+        }
+    }
+
+    pub fn sell_contract(&self, web3: &Web3<WebSocket>) -> Contract<WebSocket> {
+        Contract::new(web3.eth(), self.sell_address, self.sell_abi.clone())
+    }
+
+    pub fn buy_contract(&self, web3: &Web3<WebSocket>) -> Contract<WebSocket> {
+        Contract::new(web3.eth(), self.buy_address, self.buy_abi.clone())
+    }
+
+    pub fn filter(&self) -> Filter {
+        FilterBuilder::default()
+            .address(vec![self.sell_address, self.buy_address])
+            .build()
+    }
+
+    /// Logs of both contracts between two blocks, inclusive.
+    pub fn range_filter(&self, from: u64, to: u64) -> Filter {
+        FilterBuilder::default()
+            .address(vec![self.sell_address, self.buy_address])
+            .from_block(BlockNumber::Number(from.into()))
+            .to_block(BlockNumber::Number(to.into()))
+            .build()
+    }
+
+    fn parse(&self, log: &Log) -> Result<Option<EvmEvent>, Error> {
+        if log.address == self.sell_address {
+            parse_log(&self.sell_abi, log)
+        }
+        else if log.address == self.buy_address {
+            parse_log(&self.buy_abi, log)
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    /// Read the chain state a log is decoded with: the time of its block and, for events that change an order, the
+    /// value of the order on the sell contract once the block has been applied.
+    pub async fn read(&self, web3: &Web3<WebSocket>, log: &Log) -> Result<ChainReads, String> {
+        let block_hash = log.block_hash.ok_or("log has no block hash")?;
+        let block = web3.eth().block(BlockId::Hash(block_hash)).await
+            .map_err(|err| format!("reading block {:?} failed: {}", block_hash, err))?
+            .ok_or(format!("block {:?} not found", block_hash))?;
+        let order_id = match self.parse(log) {
+            Ok(Some(EvmEvent::AddToOrder(event))) => Some(event.order_id),
+            Ok(Some(EvmEvent::RemoveFromOrder(event))) => Some(event.order_id),
+            Ok(Some(EvmEvent::LockSell(event))) => Some(event.order_id),
+            Ok(Some(EvmEvent::TimeoutSell(event))) => Some(event.order_id),
+            _ => None,
+        };
+        let order_value = match order_id {
+            Some(order_id) => Some(call_view(web3, &self.sell_contract(web3), "getOrderValue", vec![Token::FixedBytes(order_id.to_vec())], Some(BlockId::Hash(block_hash))).await?.low_u128()),
+            None => None,
+        };
+        Ok(ChainReads {
+            block_time: block.timestamp.as_u64() * 1000,
+            extrinsic_hash: <[u8; 32]>::default(),
+            order_value: order_value,
+        })
+    }
+
+    /// Read the chain state for a log, retrying until it can be read. The log cannot be committed without it.
+    pub async fn read_retrying(&self, web3: &Web3<WebSocket>, log: &Log, listener: &str) -> ChainReads {
+        loop {
+            match self.read(web3, log).await {
+                Ok(reads) => return reads,
+                Err(err) => {
+                    eprintln!("{}: reading chain state for a log failed, retrying: {}", listener, err);
+                    sleep(Duration::from_secs(5)).await;
+                },
+            }
+        }
+    }
+
+    /// Decode a log with the chain state read for it. Does not touch the chain, so recorded logs decode the same way
+    /// when they are replayed.
+    pub fn decode(&self, log: &Log, reads: &ChainReads) -> Option<(EventMeta, SwapEvent)> {
+        let event = match self.parse(log) {
+            Ok(Some(event)) => event,
+            Ok(None) => return None,
+            Err(err) => {
+                println!("Failed to decode log: {:?}, log: {:?}", err, log);
+                return None;
+            }
+        };
+        println!("{:?}", event);
+        let order_value = reads.order_value.unwrap_or(0);
+
+        let event = match event {
+            EvmEvent::AddToOrder(event) => {
+                let order = OrderStatic {
+                    seller: event.seller,
+                    chain_id: event.chain_id,
+                    adapter_id: event.adapter_id,
+                    asset_id: event.asset_id,
+                    price: event.price,
+                    foreign_address: event.foreign_address,
+                };
+                // Never store an order under an id the contract would not have derived.
+                if order.get_evm_order_id() != event.order_id {
+                    println!("AddToOrder: order_id mismatch, expected {:?}, ignoring log: {:?}", hex::encode(order.get_evm_order_id()), log);
+                    return None;
+                }
+                SwapEvent::AddToOrder {
+                    order_id: event.order_id,
+                    seller: order.seller,
+                    chain_id: order.chain_id,
+                    adapter_id: order.adapter_id,
+                    asset_id: order.asset_id,
+                    price: order.price,
+                    foreign_address: order.foreign_address,
+                    value: event.value,
+                    order_value: order_value,
+                }
+            },
+            EvmEvent::RemoveFromOrder(event) => SwapEvent::RemoveFromOrder {
+                order_id: event.order_id,
+                value: event.value,
+                order_value: order_value,
+            },
+            EvmEvent::LockSell(event) => SwapEvent::LockSell {
+                order_id: event.order_id,
+                hashed_secret: event.hashed_secret,
+                timeout: event.timeout,
+                value: event.value,
+                order_value: order_value,
+            },
+            EvmEvent::UnlockSell(event) => SwapEvent::UnlockSell {
+                order_id: event.order_id,
+                hashed_secret: keccak_256(&event.secret),
+                secret: event.secret,
+            },
+            EvmEvent::TimeoutSell(event) => SwapEvent::TimeoutSell {
+                order_id: event.order_id,
+                hashed_secret: event.hashed_secret,
+                order_value: order_value,
+            },
+            EvmEvent::LockBuy(event) => SwapEvent::LockBuy {
+                chain_id: event.chain_id,
+                adapter_id: event.adapter_id,
+                order_id: event.order_id,
+                buyer: event.buyer,
+                seller: event.seller,
+                hashed_secret: event.hashed_secret,
+                timeout: event.timeout,
+                value: event.value,
+                foreign_address: event.foreign_address,
+                verified: true,
+            },
+            EvmEvent::UnlockBuy(event) => SwapEvent::UnlockBuy {
+                buyer: event.buyer,
+                hashed_secret: keccak_256(&event.secret),
+            },
+            EvmEvent::TimeoutBuy(event) => SwapEvent::TimeoutBuy {
+                buyer: event.buyer,
+                hashed_secret: event.hashed_secret,
+            },
+        };
+
+        let meta = EventMeta {
+            chain_id: self.chain_id,
+            adapter_id: self.adapter_id,
+            block_number: log.block_number.unwrap().as_u64(),
+            block_time: reads.block_time,
+            tx_hash: log.transaction_hash.unwrap().0,
+        };
+        Some((meta, event))
+    }
+}
+
+/// Read the state of an audit scope from the sell and buy contracts of an EVM chain at a block.
+pub async fn read_chain_state(web3: &Web3<WebSocket>, sell_contract: &Contract<WebSocket>, buy_contract: &Contract<WebSocket>, scope: &AuditScope, block_number: u64) -> Result<ChainState, String> {
+    let block = Some(BlockId::Number(BlockNumber::Number(block_number.into())));
+    let mut state = ChainState::default();
+    for (order_key, _value) in scope.orders.iter() {
+        let chain = call_view(web3, sell_contract, "getOrderValue", vec![Token::FixedBytes(order_key.order_id.to_vec())], block).await?;
+        state.order_values.insert(order_key.order_id, chain.low_u128());
+    }
+    // The buyer's address in a sell lock is the foreign address of their buy lock.
+    for lock in scope.sell_locks.iter() {
+        let chain = call_view(web3, sell_contract, "getSellLock", vec![
+            Token::FixedBytes(lock.buy_lock.order_id.to_vec()),
+            Token::FixedBytes(lock.lock_key.hashed_secret.to_vec()),
+            address_token(&lock.buy_lock.foreign_address),
+            Token::Uint(U256::from(lock.sell_lock.timeout)),
+        ], block).await?;
+        state.sell_locks.insert(lock.lock_key.hashed_secret, chain.low_u128());
+    }
+    for lock in scope.buy_locks.iter() {
+        let chain = call_view(web3, buy_contract, "getBuyLock", vec![
+            address_token(&lock.buy_lock.buyer),
+            address_token(&lock.order_static.foreign_address),
+            Token::FixedBytes(lock.lock_key.hashed_secret.to_vec()),
+            Token::Uint(U256::from(lock.buy_lock.timeout)),
+        ], block).await?;
+        state.buy_locks.insert(lock.lock_key.hashed_secret, chain.low_u128());
+    }
+    Ok(state)
+}
+
+/// Index a log with the chain state read for it. The sync cursor is committed with it.
+pub fn commit_log(db: &dyn OrderStore, tx: &Sender<RequestMessage>, recorder: &Option<Arc<Recorder>>, listener: &str, decoder: &EvmDecoder, log: &Log, reads: ChainReads) {
+    let block_number = log.block_number.unwrap().as_u64();
+    let decoded = decoder.decode(log, &reads);
+    if let Some(recorder) = recorder {
+        recorder.record(&RecordedEvent {
+            listener: listener.to_string(),
+            block_number: block_number,
+            raw: RawEvent::Evm(log.clone()),
+            reads: reads,
+        });
+    }
+    let mut batch = EventBatch::new(db);
+    if let Some((meta, swap_event)) = &decoded {
+        process_event(&mut batch, meta, swap_event);
+    }
+    batch.set_sync_cursor(listener, block_number);
+    batch.commit(tx);
+}
+
+/// Index the logs of every block from the sync cursor up to the head, in ranges a node will serve. Returns the block
+/// indexed up to, or None when there is no sync cursor to start from.
+///
+/// The cursor is set as each log is committed, so only some of the logs in its block may have been indexed. The block
+/// is indexed again, which leaves the events that were already processed as they are.
+async fn backfill(db: &dyn OrderStore, tx: &Sender<RequestMessage>, recorder: &Option<Arc<Recorder>>, listener: &str, web3: &Web3<WebSocket>, decoder: &EvmDecoder) -> Option<u64> {
+    let mut from = db.get_sync_cursor(listener)?;
+    loop {
+        let head = web3.eth().block_number().await.unwrap().as_u64();
+        if from > head {
+            return Some(head);
+        }
+        let to = std::cmp::min(from + LOG_RANGE - 1, head);
+        println!("{}: indexing blocks {} to {}.", listener, from, to);
+        for log in web3.eth().logs(decoder.range_filter(from, to)).await.unwrap() {
+            let reads = decoder.read_retrying(web3, &log, listener).await;
+            commit_log(db, tx, recorder, listener, decoder, &log, reads);
+        }
+        // Blocks without any logs have been indexed too.
+        let mut batch = EventBatch::new(db);
+        batch.set_sync_cursor(listener, to);
+        batch.commit(tx);
+        from = to + 1;
+    }
+}
+
+/// Index the sell and buy contracts of an EVM chain, carrying on from the sync cursor.
+pub async fn evm_listen(db: Arc<dyn OrderStore>, tx: Sender<RequestMessage>, recorder: Option<Arc<Recorder>>, listener: &str, web3: Web3<WebSocket>, decoder: EvmDecoder) {
+    backfill(&*db, &tx, &recorder, listener, &web3, &decoder).await;
+    let mut sub = web3.eth_subscribe().subscribe_logs(decoder.filter()).await.unwrap();
+    // Logs from before the subscription started are indexed from the node's history, and skipped when they arrive.
+    let indexed = backfill(&*db, &tx, &recorder, listener, &web3, &decoder).await;
+
+    while let Some(log) = sub.next().await {
+        let log = log.unwrap();
+        if let Some(indexed) = indexed {
+            if log.block_number.unwrap().as_u64() <= indexed { continue };
+        }
+        let reads = decoder.read_retrying(&web3, &log, listener).await;
+        commit_log(&*db, &tx, &recorder, listener, &decoder, &log, reads);
+    }
+}
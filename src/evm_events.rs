@@ -0,0 +1,204 @@
+use web3::ethabi::{Contract as Abi, Error, Log as AbiLog, RawLog, Token};
+use web3::types::{Log, U256};
+
+use crate::shared::*;
+
+/// AddToOrder event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AddToOrderEvent {
+    pub order_id: [u8; 16],
+    pub seller: [u8; 32],
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub asset_id: [u8; 8],
+    pub price: u128,
+    pub foreign_address: [u8; 32],
+    pub value: u128,
+}
+
+/// RemoveFromOrder event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RemoveFromOrderEvent {
+    pub order_id: [u8; 16],
+    pub value: u128,
+}
+
+/// LockSell event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct LockSellEvent {
+    pub order_id: [u8; 16],
+    pub hashed_secret: [u8; 32],
+    pub timeout: u128,
+    pub value: u128,
+}
+
+/// UnlockSell event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnlockSellEvent {
+    pub order_id: [u8; 16],
+    pub secret: [u8; 32],
+}
+
+/// TimeoutSell event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TimeoutSellEvent {
+    pub order_id: [u8; 16],
+    pub hashed_secret: [u8; 32],
+}
+
+/// LockBuy event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct LockBuyEvent {
+    pub buyer: [u8; 32],
+    pub seller: [u8; 32],
+    pub hashed_secret: [u8; 32],
+    pub timeout: u128,
+    pub value: u128,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub order_id: [u8; 16],
+    pub foreign_address: [u8; 32],
+}
+
+/// UnlockBuy event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct UnlockBuyEvent {
+    pub buyer: [u8; 32],
+    pub secret: [u8; 32],
+}
+
+/// TimeoutBuy event.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TimeoutBuyEvent {
+    pub buyer: [u8; 32],
+    pub hashed_secret: [u8; 32],
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum EvmEvent {
+    AddToOrder(AddToOrderEvent),
+    RemoveFromOrder(RemoveFromOrderEvent),
+    LockSell(LockSellEvent),
+    UnlockSell(UnlockSellEvent),
+    TimeoutSell(TimeoutSellEvent),
+    LockBuy(LockBuyEvent),
+    UnlockBuy(UnlockBuyEvent),
+    TimeoutBuy(TimeoutBuyEvent),
+}
+
+fn param(log: &AbiLog, name: &str) -> Result<Token, Error> {
+    log.params.iter()
+        .find(|param| param.name == name)
+        .map(|param| param.value.clone())
+        .ok_or(Error::InvalidData)
+}
+
+fn bytes16(token: Token) -> Result<[u8; 16], Error> {
+    match token {
+        Token::FixedBytes(bytes) if bytes.len() == 16 => Ok(vector_as_u8_16_array(&bytes)),
+        _ => Err(Error::InvalidData),
+    }
+}
+
+fn bytes32(token: Token) -> Result<[u8; 32], Error> {
+    match token {
+        Token::FixedBytes(bytes) if bytes.len() == 32 => Ok(vector_as_u8_32_array(&bytes)),
+        _ => Err(Error::InvalidData),
+    }
+}
+
+/// Addresses are stored as the last 20 bytes of a 32 byte word, like Substrate account ids.
+fn address(token: Token) -> Result<[u8; 32], Error> {
+    match token {
+        Token::Address(address) => {
+            let mut word = [0u8; 32];
+            word[12..].copy_from_slice(address.as_bytes());
+            Ok(word)
+        },
+        _ => Err(Error::InvalidData),
+    }
+}
+
+/// Amounts and timeouts are u128 everywhere else, larger values are rejected rather than truncated.
+fn uint128(token: Token) -> Result<u128, Error> {
+    match token {
+        Token::Uint(value) if value <= U256::from(u128::MAX) => Ok(value.low_u128()),
+        _ => Err(Error::InvalidData),
+    }
+}
+
+/// Decode a log emitted by a contract with this ABI. Logs of events the indexer does not handle are Ok(None).
+pub fn parse_log(abi: &Abi, log: &Log) -> Result<Option<EvmEvent>, Error> {
+    let topic = match log.topics.get(0) {
+        Some(topic) => topic,
+        None => return Ok(None),
+    };
+    let event = match abi.events().find(|event| event.signature() == *topic) {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+    let params = event.parse_log(RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    })?;
+
+    let event = match event.name.as_str() {
+        "AddToOrder" => {
+            // chainId (4 bytes), adapterId (4 bytes), assetId (8 bytes), price (16 bytes)
+            let packed = bytes32(param(&params, "chainIdAdapterIdAssetIdPrice")?)?;
+            EvmEvent::AddToOrder(AddToOrderEvent {
+                order_id: bytes16(param(&params, "orderId")?)?,
+                seller: address(param(&params, "seller")?)?,
+                chain_id: u32::from_be_bytes(vector_as_u8_4_array(&packed[0..4].to_vec())),
+                adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&packed[4..8].to_vec())),
+                asset_id: vector_as_u8_8_array(&packed[8..16].to_vec()),
+                price: u128::from_be_bytes(vector_as_u8_16_array(&packed[16..32].to_vec())),
+                foreign_address: bytes32(param(&params, "foreignAddress")?)?,
+                value: uint128(param(&params, "value")?)?,
+            })
+        },
+        "RemoveFromOrder" => EvmEvent::RemoveFromOrder(RemoveFromOrderEvent {
+            order_id: bytes16(param(&params, "orderId")?)?,
+            value: uint128(param(&params, "value")?)?,
+        }),
+        "LockSell" => EvmEvent::LockSell(LockSellEvent {
+            order_id: bytes16(param(&params, "orderId")?)?,
+            hashed_secret: bytes32(param(&params, "hashedSecret")?)?,
+            timeout: uint128(param(&params, "timeout")?)?,
+            value: uint128(param(&params, "value")?)?,
+        }),
+        "UnlockSell" => EvmEvent::UnlockSell(UnlockSellEvent {
+            order_id: bytes16(param(&params, "orderId")?)?,
+            secret: bytes32(param(&params, "secret")?)?,
+        }),
+        "TimeoutSell" => EvmEvent::TimeoutSell(TimeoutSellEvent {
+            order_id: bytes16(param(&params, "orderId")?)?,
+            hashed_secret: bytes32(param(&params, "hashedSecret")?)?,
+        }),
+        "LockBuy" => {
+            // chainId (4 bytes), adapterId (4 bytes), orderId (16 bytes)
+            let packed = bytes32(param(&params, "chainIdAdapterIdOrderId")?)?;
+            EvmEvent::LockBuy(LockBuyEvent {
+                buyer: address(param(&params, "buyer")?)?,
+                seller: address(param(&params, "seller")?)?,
+                hashed_secret: bytes32(param(&params, "hashedSecret")?)?,
+                timeout: uint128(param(&params, "timeout")?)?,
+                value: uint128(param(&params, "value")?)?,
+                chain_id: u32::from_be_bytes(vector_as_u8_4_array(&packed[0..4].to_vec())),
+                adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&packed[4..8].to_vec())),
+                order_id: vector_as_u8_16_array(&packed[8..24].to_vec()),
+                foreign_address: bytes32(param(&params, "foreignAddress")?)?,
+            })
+        },
+        "UnlockBuy" => EvmEvent::UnlockBuy(UnlockBuyEvent {
+            buyer: address(param(&params, "buyer")?)?,
+            secret: bytes32(param(&params, "secret")?)?,
+        }),
+        "TimeoutBuy" => EvmEvent::TimeoutBuy(TimeoutBuyEvent {
+            buyer: address(param(&params, "buyer")?)?,
+            hashed_secret: bytes32(param(&params, "hashedSecret")?)?,
+        }),
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
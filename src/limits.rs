@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::config::{Quota, WebsocketConfig};
+
+/// Decide whether a websocket handshake is accepted, and with which quota. Clients with a valid API key get the key's
+/// quota and may connect from any origin.
+pub fn check_handshake(config: &WebsocketConfig, origin: Option<&str>, api_key: Option<&str>) -> Result<Quota, String> {
+    if let Some(api_key) = api_key {
+        return match config.api_keys.iter().find(|key| key.key == api_key) {
+            Some(key) => Ok(key.quota.clone()),
+            None => Err("Unknown API key.".to_string()),
+        };
+    }
+    if !config.allowed_origins.is_empty() {
+        match origin {
+            Some(origin) if config.allowed_origins.iter().any(|allowed| allowed == origin) => {},
+            _ => return Err(format!("Origin not allowed: {:?}", origin)),
+        }
+    }
+    Ok(config.quota.clone())
+}
+
+/// Counts the open connections from each IP address.
+#[derive(Default)]
+pub struct ConnectionLimiter {
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Held for as long as a connection is open.
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    /// Count a new connection, unless the address already has `max` open.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr, max: usize) -> Option<ConnectionGuard> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip: ip,
+        })
+    }
+
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.counts.lock().unwrap().get(&ip).cloned().unwrap_or(0)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Token bucket limiting the requests on one connection.
+pub struct RateLimiter {
+    requests_per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(quota: &Quota) -> RateLimiter {
+        RateLimiter {
+            requests_per_second: quota.requests_per_second,
+            burst: quota.burst,
+            tokens: quota.burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token for a request made at `now`, returning false if there are none left.
+    pub fn check_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.requests_per_second).min(self.burst);
+        self.last = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    pub fn check(&mut self) -> bool {
+        self.check_at(Instant::now())
+    }
+}
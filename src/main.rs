@@ -1,45 +1,128 @@
 use tokio::join;
 use tokio::sync::broadcast;
-use rocksdb::{DB, ColumnFamilyDescriptor, Options};
 use std::sync::Arc;
+use std::env;
+use std::process;
 
 mod shared;
+mod db;
+mod config;
 mod websockets;
-mod acuity;
+mod substrate;
+mod substrate_events;
+mod limits;
+mod tls;
 mod ethereum;
 mod arbitrum;
+mod trades;
+mod candles;
+mod assets;
+mod markets;
+mod evm;
+mod evm_events;
+mod auditor;
+mod snapshot;
+mod migrations;
+mod store;
+mod processor;
+mod recorder;
+mod admin;
+mod rebuild;
+mod bitcoin;
 
+use db::RocksDbStore;
+use store::OrderStore;
 use websockets::websockets_listen;
-use acuity::acuity_listen;
-use ethereum::ethereum_listen;
-use arbitrum::arbitrum_listen;
+use substrate::substrate_listen;
+use ethereum::{ethereum_listen, ethereum_decoder};
+use arbitrum::{arbitrum_listen, arbitrum_decoder};
+use bitcoin::bitcoin_listen;
+use config::Config;
+use assets::load_assets;
+use auditor::auditor_run;
+use snapshot::{export_snapshot, import_snapshot};
+use migrations::migrate;
+use recorder::{Recorder, ReplayDecoders, replay};
+use tls::Tls;
+use admin::admin;
 
 #[cfg(test)]
 mod tests;
 
+fn usage(command: &str) -> ! {
+    eprintln!("Usage: acuity-atomic-swap-offchain {}", command);
+    process::exit(1);
+}
+
 #[tokio::main]
 async fn main() {
-    let mut db_opts = Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.create_missing_column_families(true);
-    let path = "database";
-    let cf1 = ColumnFamilyDescriptor::new("order_static", Options::default());
-    let cf2 = ColumnFamilyDescriptor::new("order_value", Options::default());
-    let cf3 = ColumnFamilyDescriptor::new("order_list", Options::default());
-    let cf4 = ColumnFamilyDescriptor::new("order_lock_list", Options::default());
-    let cf5 = ColumnFamilyDescriptor::new("buy_lock", Options::default());
-    let cf6 = ColumnFamilyDescriptor::new("sell_lock", Options::default());
-    let db = DB::open_cf_descriptors(&db_opts, path, vec![cf1, cf2, cf3, cf4, cf5, cf6]).unwrap();
-    let db = Arc::new(db);
-    let (tx, _rx) = broadcast::channel(16);
-    // Spawn Acuity task.
-    let acuity_task = tokio::spawn(acuity_listen(db.clone(), tx.clone()));
+    let config = Arc::new(Config::load("config.json"));
+    let args: Vec<String> = env::args().collect();
+
+    // Replay into its own database so the indexer's database is left alone.
+    if args.get(1).map(|arg| arg.as_str()) == Some("replay") {
+        let (recording, database) = match (args.get(2), args.get(3)) {
+            (Some(recording), Some(database)) => (recording, database),
+            _ => usage("replay <recording> <database>"),
+        };
+        let db = RocksDbStore::open(database);
+        migrate(&db);
+        let decoders = ReplayDecoders {
+            evm: vec![("ethereum".to_string(), ethereum_decoder()), ("arbitrum".to_string(), arbitrum_decoder())],
+            substrate: config.substrate.clone(),
+        };
+        return replay(&db, recording, &decoders);
+    }
+
+    // Admin commands must not take the lock on a running indexer's database.
+    if args.get(1).map(|arg| arg.as_str()) == Some("admin") {
+        return admin("database", &args[2..]);
+    }
+
+    let db = RocksDbStore::open("database");
+    migrate(&db);
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("export") => return export_snapshot(&db, args.get(2).unwrap_or_else(|| usage("export <file>"))),
+        Some("import") => return import_snapshot(&db, args.get(2).unwrap_or_else(|| usage("import <file>"))),
+        Some(command) => panic!("Unknown command: {}", command),
+        None => {},
+    }
+
+    let db: Arc<dyn OrderStore> = Arc::new(db);
+    load_assets(&*db, &config.assets);
+    let (tx, _rx) = broadcast::channel(config.websocket.broadcast_capacity);
+    let recorder = match config.recorder.enabled {
+        true => Some(Arc::new(Recorder::create(&config.recorder.path))),
+        false => None,
+    };
+    // Spawn a task for each Substrate chain.
+    for substrate_config in config.substrate.iter() {
+        tokio::spawn(substrate_listen(db.clone(), tx.clone(), recorder.clone(), substrate_config.clone()));
+    }
     // Spawn Ethereum task.
-    let ethereum_task = tokio::spawn(ethereum_listen(db.clone(), tx.clone()));
+    let ethereum_task = tokio::spawn(ethereum_listen(db.clone(), tx.clone(), recorder.clone()));
     // Spawn Ethereum task.
-    let arbitrum_task = tokio::spawn(arbitrum_listen(db.clone(), tx.clone()));
+    let arbitrum_task = tokio::spawn(arbitrum_listen(db.clone(), tx.clone(), recorder));
+    // Spawn a task for each Bitcoin family chain.
+    for bitcoin_config in config.bitcoin.iter() {
+        tokio::spawn(bitcoin_listen(db.clone(), tx.clone(), bitcoin_config.clone()));
+    }
+    // Spawn auditor task.
+    if config.audit.enabled {
+        tokio::spawn(auditor_run(db.clone(), tx.clone(), config.audit.clone(), config.substrate.clone()));
+    }
+    // Load the TLS certificate shared by every server, and reload it when it is renewed.
+    let tls = match config.tls.enabled {
+        true => {
+            let tls = Tls::new(&config.tls);
+            tokio::spawn(tls.clone().watch(config.tls.clone()));
+            Some(tls)
+        },
+        false => None,
+    };
     // Spawn websockets task.
-    let websockets_task = tokio::spawn(websockets_listen(db.clone(), tx));
+    let websockets_task = tokio::spawn(websockets_listen(db.clone(), tx, config.clone(), tls));
     // Wait to exit.
-    let _result = join!(websockets_task, acuity_task, arbitrum_task, ethereum_task);
+    let _result = join!(websockets_task, arbitrum_task, ethereum_task);
 }
@@ -0,0 +1,75 @@
+use crate::shared::*;
+use crate::store::OrderStore;
+
+fn get_price(store: &dyn OrderStore, key: &OrderListKey) -> u128 {
+    let order_key = OrderKey {
+        chain_id: key.sell_chain_id,
+        adapter_id: key.sell_adapter_id,
+        order_id: key.order_id,
+    };
+    store.get_order(&order_key).unwrap().price
+}
+
+/// Update the market index for an order that has been added to order_list.
+pub fn add_to_market(store: &dyn OrderStore, key: &OrderListKey) {
+    let market_key = key.market();
+    let mut market = store.get_market(&market_key).unwrap_or_default();
+    market.order_count += 1;
+    market.total_value += key.value;
+    store.put_market(&market_key, &market);
+
+    store.put_market_price(&MarketPriceKey {
+        market: market_key,
+        price: get_price(store, key),
+        sell_adapter_id: key.sell_adapter_id,
+        order_id: key.order_id,
+    });
+}
+
+/// Update the market index for an order that has been removed from order_list.
+pub fn remove_from_market(store: &dyn OrderStore, key: &OrderListKey) {
+    let market_key = key.market();
+    let mut market = store.get_market(&market_key).unwrap_or_default();
+    market.order_count = market.order_count.saturating_sub(1);
+    market.total_value = market.total_value.saturating_sub(key.value);
+    if market.order_count == 0 {
+        store.delete_market(&market_key);
+    } else {
+        store.put_market(&market_key, &market);
+    }
+
+    store.delete_market_price(&MarketPriceKey {
+        market: market_key,
+        price: get_price(store, key),
+        sell_adapter_id: key.sell_adapter_id,
+        order_id: key.order_id,
+    });
+}
+
+/// Lowest price of any order in the market.
+pub fn get_best_price(store: &dyn OrderStore, market_key: &MarketKey) -> Option<u128> {
+    let start_key = MarketPriceKey {
+        market: market_key.clone(),
+        price: u128::default(),
+        sell_adapter_id: u32::default(),
+        order_id: <[u8; 16]>::default(),
+    };
+    match store.iter_from("market_price", &start_key.serialize()).next() {
+        Some((key, _value)) => {
+            let market_price_key = MarketPriceKey::unserialize(key);
+            if market_price_key.market != *market_key { return None };
+            Some(market_price_key.price)
+        },
+        None => None,
+    }
+}
+
+pub fn list_markets(store: &dyn OrderStore) -> Vec<(MarketKey, Market, Option<u128>)> {
+    store.iter("market")
+        .map(|(key, value)| {
+            let market_key = MarketKey::unserialize(key);
+            let best_price = get_best_price(store, &market_key);
+            (market_key, bincode::deserialize(&value).unwrap(), best_price)
+        })
+        .collect()
+}
@@ -0,0 +1,150 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::shared::*;
+use crate::store::{OrderStore, EventBatch, COLUMN_FAMILIES};
+use crate::markets::add_to_market;
+use crate::rebuild::rebuild_indexes;
+
+/// Schema version of databases written by this build.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// MIGRATIONS[n] transforms a database from schema version n to n + 1.
+const MIGRATIONS: [fn(&dyn OrderStore); SCHEMA_VERSION as usize] = [
+    migrate_0_to_1,
+    migrate_1_to_2,
+];
+
+const ETHEREUM_CHAIN_ID: u32 = 9001;
+const ARBITRUM_CHAIN_ID: u32 = 421611;
+
+/// Version 1 adds the market index. Build it from the orders already in order_list, replacing anything a partial run
+/// left behind so counts and totals are not added twice.
+fn migrate_0_to_1(store: &dyn OrderStore) {
+    let batch = EventBatch::new(store);
+    for cf in ["market", "market_price"].iter() {
+        for (key, _value) in store.iter(*cf) {
+            batch.delete(*cf, &key);
+        }
+    }
+    let keys = store.iter("order_list")
+        .map(|(key, _value)| OrderListKey::unserialize(key))
+        .collect::<Vec<_>>();
+    for key in keys {
+        add_to_market(&batch, &key);
+    }
+    batch.flush();
+
+    // Records kept the formats they had before schema versioning. Check every one can be read now, instead of
+    // panicking on it while indexing.
+    migrate_records(store, "order_static", |order_static: OrderStatic| order_static);
+    migrate_records(store, "buy_lock", |buy_lock: BuyLock| buy_lock);
+    migrate_records(store, "sell_lock", |sell_lock: SellLock| sell_lock);
+}
+
+/// Column families keyed by the chain of the listener that wrote them, rather than by a chain id read from an event.
+const LISTENER_KEYED_COLUMN_FAMILIES: [&str; 6] = [
+    "order_static",
+    "order_value",
+    "sell_lock",
+    "trade",
+    "pending_unlock",
+    "market_sequence",
+];
+
+/// Move the records of a column family whose keys start with one chain id to another.
+fn move_chain(store: &dyn OrderStore, cf: &'static str, from: u32, to: u32) -> usize {
+    let batch = EventBatch::new(store);
+    let mut count = 0;
+    for (key, value) in store.iter(cf) {
+        if key[0..4] != from.to_be_bytes() { continue };
+        let mut new_key = key.clone();
+        new_key[0..4].copy_from_slice(&to.to_be_bytes());
+        batch.delete(cf, &key);
+        batch.put(cf, &new_key, &value);
+        count += 1;
+    }
+    batch.flush();
+    count
+}
+
+/// Version 2 keys Arbitrum orders with chain id 421611. Before, the Arbitrum listener keyed them with Ethereum's 9001.
+/// Order ids on EVM chains do not include the contract, so the rows can only be moved when Ethereum has never been
+/// indexed into the database: the Arbitrum listener has a sync cursor and the Ethereum listener does not. Otherwise
+/// the rows are left under 9001 and the database has to be resynced.
+///
+/// Version 2 also adds buy_lock_secret, which the derived indexes are rebuilt to fill.
+fn migrate_1_to_2(store: &dyn OrderStore) {
+    let sync_cursors = store.list_sync_cursors();
+    let has_rows = LISTENER_KEYED_COLUMN_FAMILIES.iter()
+        .any(|cf| store.iter(*cf).any(|(key, _value)| key[0..4] == ETHEREUM_CHAIN_ID.to_be_bytes()));
+    if has_rows && sync_cursors.contains_key("arbitrum") && !sync_cursors.contains_key("ethereum") {
+        for cf in LISTENER_KEYED_COLUMN_FAMILIES.iter() {
+            let count = move_chain(store, *cf, ETHEREUM_CHAIN_ID, ARBITRUM_CHAIN_ID);
+            println!("Moved {} {} records from chain {} to {}.", count, cf, ETHEREUM_CHAIN_ID, ARBITRUM_CHAIN_ID);
+        }
+        migrate_records(store, "trade", |mut trade: Trade| {
+            if trade.sell_chain_id == ETHEREUM_CHAIN_ID {
+                trade.sell_chain_id = ARBITRUM_CHAIN_ID;
+            }
+            trade
+        });
+    }
+    else if has_rows {
+        eprintln!("Records keyed with chain {} may be from Ethereum or Arbitrum and have not been moved. Resync the database to key Arbitrum orders with chain {}.", ETHEREUM_CHAIN_ID, ARBITRUM_CHAIN_ID);
+    }
+
+    for (cf, count) in rebuild_indexes(store) {
+        println!("Rebuilt {}: {:?}", cf, count);
+    }
+}
+
+/// Rewrite every record of a column family stored with bincode from its format at an older schema version to the
+/// current one, returning how many were rewritten.
+///
+/// OrderStatic, BuyLock, SellLock and the other bincode records have no field names or lengths on disk, so a change to
+/// one of them makes every existing record unreadable. Such a change bumps SCHEMA_VERSION and adds a migration that
+/// keeps a copy of the old struct and converts each record with this. The formats written by the current schema
+/// version are pinned by fixtures in the tests.
+pub fn migrate_records<Old: DeserializeOwned, New: Serialize>(store: &dyn OrderStore, cf: &'static str, convert: impl Fn(Old) -> New) -> usize {
+    let batch = EventBatch::new(store);
+    let mut count = 0;
+    for (key, value) in store.iter(cf) {
+        let old: Old = match bincode::deserialize(&value) {
+            Ok(old) => old,
+            Err(err) => panic!("Unreadable {} record {}: {}", cf, hex::encode(&key), err),
+        };
+        batch.put(cf, &key, &bincode::serialize(&convert(old)).unwrap());
+        count += 1;
+    }
+    batch.flush();
+    count
+}
+
+/// True when no column family other than metadata has any entries.
+pub fn is_empty(store: &dyn OrderStore) -> bool {
+    COLUMN_FAMILIES.iter()
+        .filter(|cf| **cf != "metadata")
+        .all(|cf| store.iter(*cf).next().is_none())
+}
+
+/// Bring the database up to SCHEMA_VERSION. Databases from before schema versioning are version 0.
+pub fn migrate(store: &dyn OrderStore) {
+    let mut version = match store.get_schema_version() {
+        Some(version) => version,
+        None if is_empty(store) => SCHEMA_VERSION,
+        None => 0,
+    };
+
+    if version > SCHEMA_VERSION {
+        panic!("Database schema version {} is newer than this indexer supports ({}).", version, SCHEMA_VERSION);
+    }
+
+    while version < SCHEMA_VERSION {
+        println!("Migrating database from schema version {} to {}.", version, version + 1);
+        MIGRATIONS[version as usize](store);
+        version += 1;
+        store.set_schema_version(version);
+    }
+    store.set_schema_version(version);
+}
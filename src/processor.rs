@@ -0,0 +1,414 @@
+use serde::{Serialize, Deserialize};
+
+use crate::shared::*;
+use crate::store::{OrderStore, EventBatch};
+use crate::trades::{record_unlock, attach_pending_unlock, TradeSide};
+use crate::candles::update_candles;
+use crate::markets::{add_to_market, remove_from_market};
+
+/// Where and when an event was emitted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventMeta {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub block_number: u64,
+    pub block_time: u64,        // milliseconds
+    pub tx_hash: [u8; 32],
+}
+
+impl EventMeta {
+    pub fn trade_leg(&self) -> TradeLeg {
+        TradeLeg {
+            block_number: self.block_number,
+            block_time: self.block_time,
+            tx_hash: self.tx_hash,
+        }
+    }
+}
+
+/// An AtomicSwap event decoded from any chain.
+///
+/// Sell side events refer to orders selling on the chain in the EventMeta. LockBuy, VerifyBuy and InvalidBuy refer to orders on
+/// the chain given in the event. UnlockBuy and TimeoutBuy only have the hashed secret, and are matched to the buy lock made
+/// with it on the chain in the EventMeta. `order_value` is the value of the order after the event, read from chain state
+/// by the decoder.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SwapEvent {
+    AddToOrder {
+        order_id: [u8; 16],
+        seller: [u8; 32],
+        chain_id: u32,
+        adapter_id: u32,
+        asset_id: [u8; 8],
+        price: u128,
+        foreign_address: [u8; 32],
+        value: u128,
+        order_value: u128,
+    },
+    RemoveFromOrder {
+        order_id: [u8; 16],
+        value: u128,
+        order_value: u128,
+    },
+    LockSell {
+        order_id: [u8; 16],
+        hashed_secret: [u8; 32],
+        timeout: u128,
+        value: u128,
+        order_value: u128,
+    },
+    UnlockSell {
+        order_id: [u8; 16],
+        hashed_secret: [u8; 32],
+        secret: [u8; 32],
+    },
+    TimeoutSell {
+        order_id: [u8; 16],
+        hashed_secret: [u8; 32],
+        order_value: u128,
+    },
+    LockBuy {
+        chain_id: u32,
+        adapter_id: u32,
+        order_id: [u8; 16],
+        buyer: [u8; 32],
+        seller: [u8; 32],
+        hashed_secret: [u8; 32],
+        timeout: u128,
+        value: u128,
+        foreign_address: [u8; 32],
+        // False when the lock cannot be checked until it is spent.
+        verified: bool,
+    },
+    UnlockBuy {
+        buyer: [u8; 32],
+        hashed_secret: [u8; 32],
+    },
+    TimeoutBuy {
+        buyer: [u8; 32],
+        hashed_secret: [u8; 32],
+    },
+    // The script of a buy lock that could not be checked when it was made has been revealed. The buyer and seller
+    // are known from it.
+    VerifyBuy {
+        chain_id: u32,
+        adapter_id: u32,
+        hashed_secret: [u8; 32],
+        buyer: [u8; 32],
+        seller: [u8; 32],
+        timeout: u128,
+    },
+    // A buy lock turned out not to lock what it claimed to.
+    InvalidBuy {
+        chain_id: u32,
+        adapter_id: u32,
+        hashed_secret: [u8; 32],
+    },
+}
+
+fn notify_order(batch: &mut EventBatch, order_key: &OrderKey) {
+    batch.notify(RequestMessage::GetOrder {
+        sell_chain_id: order_key.chain_id,
+        sell_adapter_id: order_key.adapter_id,
+        order_id: hex::encode(order_key.order_id),
+    });
+}
+
+/// Key of an order in order_list when it has this value.
+pub fn order_list_key(order_key: &OrderKey, order_static: &OrderStatic, value: u128) -> OrderListKey {
+    OrderListKey {
+        sell_chain_id: order_key.chain_id,
+        sell_asset_id: <[u8; 8]>::default(),
+        buy_chain_id: order_static.chain_id,
+        buy_asset_id: order_static.asset_id,
+        value: value,
+        sell_adapter_id: order_key.adapter_id,
+        order_id: order_key.order_id,
+    }
+}
+
+/// Publish a change to a market's order book. The delta gets the market's next sequence number when the batch is
+/// committed.
+fn notify_order_book_delta(batch: &mut EventBatch, key: &OrderListKey, change: OrderBookChange) {
+    batch.notify(RequestMessage::OrderBookDelta {
+        market: key.market(),
+        sequence: 0,
+        change: change,
+        sell_adapter_id: key.sell_adapter_id,
+        order_id: key.order_id,
+        value: key.value,
+    });
+}
+
+/// Key of the buy lock made on the chain in the EventMeta with this hashed secret.
+fn find_buy_lock_key(batch: &EventBatch, meta: &EventMeta, hashed_secret: [u8; 32]) -> Option<LockKey> {
+    let lock_key = batch.get_buy_lock_key(&LockKey {
+        chain_id: meta.chain_id,
+        adapter_id: meta.adapter_id,
+        hashed_secret: hashed_secret,
+    });
+    if lock_key.is_none() {
+        println!("buy_lock not found: {:?}", hex::encode(hashed_secret));
+    }
+    lock_key
+}
+
+/// Set the value of an order, moving it within order_list and the market index. Orders with no value are removed.
+/// Changes to the order book are published as deltas.
+pub fn set_order_value(batch: &mut EventBatch, order_key: &OrderKey, value: u128) {
+    let order_static = match batch.get_order(order_key) {
+        Some(order_static) => order_static,
+        None => {
+            println!("order_static not found: {:?}", hex::encode(order_key.order_id));
+            return;
+        }
+    };
+
+    let old_value = batch.get_order_value(order_key);
+    if let Some(old_value) = old_value {
+        println!("old value: {:?}", old_value);
+        let key = order_list_key(order_key, &order_static, old_value);
+        batch.delete_order_list(&key);
+        remove_from_market(batch, &key);
+    }
+
+    println!("new value: {:?}", value);
+    let key = order_list_key(order_key, &order_static, value);
+    if value == 0 {
+        batch.delete_order_value(order_key);
+    } else {
+        batch.put_order_list(&key);
+        add_to_market(batch, &key);
+        batch.put_order_value(order_key, value);
+    }
+
+    let change = match (old_value, value) {
+        (None, 0) => return,
+        (None, _) => OrderBookChange::Added,
+        (Some(_), 0) => OrderBookChange::Removed,
+        (Some(old_value), _) if old_value == value => return,
+        (Some(_), _) => OrderBookChange::ValueChanged,
+    };
+    notify_order_book_delta(batch, &key, change);
+}
+
+/// Apply an event to the store. Notifications are queued on the batch.
+pub fn process_event(batch: &mut EventBatch, meta: &EventMeta, event: &SwapEvent) {
+    println!("{:?} {:?}", meta, event);
+
+    match *event {
+        SwapEvent::AddToOrder { order_id, seller, chain_id, adapter_id, asset_id, price, foreign_address, order_value, .. } => {
+            let order_key = OrderKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                order_id: order_id,
+            };
+            batch.put_order(&order_key, &OrderStatic {
+                seller: seller,
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                asset_id: asset_id,
+                price: price,
+                foreign_address: foreign_address,
+            });
+            set_order_value(batch, &order_key, order_value);
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::RemoveFromOrder { order_id, order_value, .. } => {
+            let order_key = OrderKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                order_id: order_id,
+            };
+            set_order_value(batch, &order_key, order_value);
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::LockSell { order_id, hashed_secret, timeout, order_value, .. } => {
+            let order_key = OrderKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                order_id: order_id,
+            };
+            let lock_key = LockKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            batch.put_sell_lock(&lock_key, &SellLock {
+                state: LockState::Locked,
+                timeout: timeout,
+                secret: None,
+            });
+            set_order_value(batch, &order_key, order_value);
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::UnlockSell { order_id, hashed_secret, secret } => {
+            let order_key = OrderKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                order_id: order_id,
+            };
+            let lock_key = LockKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            let mut sell_lock = match batch.get_sell_lock(&lock_key) {
+                Some(sell_lock) => sell_lock,
+                None => SellLock {
+                    timeout: 0,
+                    state: LockState::NotLocked,
+                    secret: None,
+                }
+            };
+            sell_lock.state = LockState::Unlocked;
+            sell_lock.secret = Some(secret);
+            batch.put_sell_lock(&lock_key, &sell_lock);
+            if let Some(trade) = record_unlock(batch, &lock_key, TradeSide::Sell, meta.trade_leg()) {
+                update_candles(batch, &trade);
+            }
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::TimeoutSell { order_id, hashed_secret, order_value } => {
+            let order_key = OrderKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                order_id: order_id,
+            };
+            let lock_key = LockKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            if let Some(mut sell_lock) = batch.get_sell_lock(&lock_key) {
+                sell_lock.state = LockState::TimedOut;
+                batch.put_sell_lock(&lock_key, &sell_lock);
+            }
+            set_order_value(batch, &order_key, order_value);
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::LockBuy { chain_id, adapter_id, order_id, buyer, hashed_secret, timeout, value, foreign_address, verified, .. } => {
+            let order_key = OrderKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                order_id: order_id,
+            };
+            batch.put_order_lock(&OrderLockListKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                order_id: order_id,
+                value: value,
+                hashed_secret: hashed_secret,
+            });
+            let lock_key = LockKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            batch.put_buy_lock(&lock_key, &BuyLock {
+                order_id: order_id,
+                value: value,
+                timeout: timeout,
+                buyer: buyer,
+                foreign_address: foreign_address,
+                state: match verified {
+                    true => LockState::Locked,
+                    false => LockState::Unverified,
+                },
+            });
+            batch.put_buy_lock_key(&LockKey {
+                chain_id: meta.chain_id,
+                adapter_id: meta.adapter_id,
+                hashed_secret: hashed_secret,
+            }, &lock_key);
+            if let Some(trade) = attach_pending_unlock(batch, &lock_key) {
+                update_candles(batch, &trade);
+            }
+            notify_order(batch, &order_key);
+        },
+        SwapEvent::UnlockBuy { hashed_secret, .. } => {
+            let lock_key = match find_buy_lock_key(batch, meta, hashed_secret) {
+                Some(lock_key) => lock_key,
+                None => return,
+            };
+            let mut buy_lock = match batch.get_buy_lock(&lock_key) {
+                Some(buy_lock) => buy_lock,
+                None => {
+                    println!("buy_lock not found: {:?}", hex::encode(hashed_secret));
+                    return;
+                }
+            };
+            buy_lock.state = LockState::Unlocked;
+            batch.put_buy_lock(&lock_key, &buy_lock);
+            if let Some(trade) = record_unlock(batch, &lock_key, TradeSide::Buy, meta.trade_leg()) {
+                update_candles(batch, &trade);
+            }
+            notify_order(batch, &OrderKey {
+                chain_id: lock_key.chain_id,
+                adapter_id: lock_key.adapter_id,
+                order_id: buy_lock.order_id,
+            });
+        },
+        SwapEvent::TimeoutBuy { hashed_secret, .. } => {
+            let lock_key = match find_buy_lock_key(batch, meta, hashed_secret) {
+                Some(lock_key) => lock_key,
+                None => return,
+            };
+            let mut buy_lock = match batch.get_buy_lock(&lock_key) {
+                Some(buy_lock) => buy_lock,
+                None => {
+                    println!("buy_lock not found: {:?}", hex::encode(hashed_secret));
+                    return;
+                }
+            };
+            buy_lock.state = LockState::TimedOut;
+            batch.put_buy_lock(&lock_key, &buy_lock);
+            notify_order(batch, &OrderKey {
+                chain_id: lock_key.chain_id,
+                adapter_id: lock_key.adapter_id,
+                order_id: buy_lock.order_id,
+            });
+        },
+        SwapEvent::VerifyBuy { chain_id, adapter_id, hashed_secret, buyer, timeout, .. } => {
+            let lock_key = LockKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            let mut buy_lock = match batch.get_buy_lock(&lock_key) {
+                Some(buy_lock) => buy_lock,
+                None => {
+                    println!("buy_lock not found: {:?}", hex::encode(hashed_secret));
+                    return;
+                }
+            };
+            buy_lock.buyer = buyer;
+            buy_lock.timeout = timeout;
+            if let LockState::Unverified = buy_lock.state {
+                buy_lock.state = LockState::Locked;
+            }
+            batch.put_buy_lock(&lock_key, &buy_lock);
+        },
+        SwapEvent::InvalidBuy { chain_id, adapter_id, hashed_secret } => {
+            let lock_key = LockKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                hashed_secret: hashed_secret,
+            };
+            let mut buy_lock = match batch.get_buy_lock(&lock_key) {
+                Some(buy_lock) => buy_lock,
+                None => {
+                    println!("buy_lock not found: {:?}", hex::encode(hashed_secret));
+                    return;
+                }
+            };
+            buy_lock.state = LockState::Invalid;
+            batch.put_buy_lock(&lock_key, &buy_lock);
+            notify_order(batch, &OrderKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                order_id: buy_lock.order_id,
+            });
+        },
+    }
+}
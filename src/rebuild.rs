@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use crate::shared::*;
+use crate::store::{OrderStore, MemoryStore, EventBatch, CfWrite};
+use crate::markets::add_to_market;
+use crate::candles::update_candles;
+use crate::processor::order_list_key;
+
+/// Column families that are only indexes over the primary records, and can be regenerated from them.
+pub const DERIVED_COLUMN_FAMILIES: [&str; 7] = [
+    "order_list",
+    "order_lock_list",
+    "buy_lock_secret",
+    "trade_list",
+    "candle",
+    "market",
+    "market_price",
+];
+
+/// Primary records the derived column families are built from.
+const SOURCE_COLUMN_FAMILIES: [&str; 4] = [
+    "order_static",
+    "order_value",
+    "buy_lock",
+    "trade",
+];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RebuildCount {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+/// Build every derived index in a scratch store from the primary records, using the same code the event processor uses.
+fn build_derived(store: &dyn OrderStore) -> MemoryStore {
+    let scratch = MemoryStore::new();
+    for cf in SOURCE_COLUMN_FAMILIES.iter() {
+        let writes = store.iter(*cf).map(|(key, value)| (*cf, key, Some(value))).collect::<Vec<CfWrite>>();
+        scratch.write(writes);
+    }
+
+    let order_values = scratch.iter("order_value").collect::<Vec<_>>();
+    for (key, value) in order_values {
+        let order_key = OrderKey::unserialize(key);
+        let order_static = match scratch.get_order(&order_key) {
+            Some(order_static) => order_static,
+            None => {
+                println!("Rebuild: order_static not found: {:?}", hex::encode(order_key.order_id));
+                continue;
+            }
+        };
+        let value = u128::from_be_bytes(vector_as_u8_16_array(&value));
+        let key = order_list_key(&order_key, &order_static, value);
+        scratch.put_order_list(&key);
+        add_to_market(&scratch, &key);
+    }
+
+    let buy_locks = scratch.iter("buy_lock").collect::<Vec<_>>();
+    for (key, value) in buy_locks {
+        let lock_key = LockKey::unserialize(key);
+        let buy_lock: BuyLock = bincode::deserialize(&value).unwrap();
+        scratch.put_order_lock(&OrderLockListKey {
+            chain_id: lock_key.chain_id,
+            adapter_id: lock_key.adapter_id,
+            order_id: buy_lock.order_id,
+            value: buy_lock.value,
+            hashed_secret: lock_key.hashed_secret,
+        });
+        // Buy locks are made on the chain the order buys from.
+        let order_static = match scratch.get_order(&OrderKey {
+            chain_id: lock_key.chain_id,
+            adapter_id: lock_key.adapter_id,
+            order_id: buy_lock.order_id,
+        }) {
+            Some(order_static) => order_static,
+            None => {
+                println!("Rebuild: order_static not found: {:?}", hex::encode(buy_lock.order_id));
+                continue;
+            }
+        };
+        scratch.put_buy_lock_key(&LockKey {
+            chain_id: order_static.chain_id,
+            adapter_id: order_static.adapter_id,
+            hashed_secret: lock_key.hashed_secret,
+        }, &lock_key);
+    }
+
+    let trades = scratch.iter("trade").collect::<Vec<_>>();
+    let mut batch = EventBatch::new(&scratch);
+    for (_key, value) in trades {
+        let trade: Trade = bincode::deserialize(&value).unwrap();
+        if !trade.is_complete() { continue };
+        batch.put_trade_list(&trade.list_key());
+        update_candles(&mut batch, &trade);
+    }
+    // Nobody is subscribed to the scratch store.
+    let _notifications = batch.flush();
+
+    scratch
+}
+
+/// Drop and regenerate every derived index from the primary column families. All changes are written atomically.
+pub fn rebuild_indexes(store: &dyn OrderStore) -> BTreeMap<&'static str, RebuildCount> {
+    let scratch = build_derived(store);
+    let mut writes: Vec<CfWrite> = Vec::new();
+    let mut counts = BTreeMap::new();
+
+    for cf in DERIVED_COLUMN_FAMILIES.iter() {
+        let mut count = RebuildCount::default();
+        let mut expected = scratch.iter(*cf).collect::<BTreeMap<_, _>>();
+        for (key, value) in store.iter(*cf) {
+            match expected.remove(&key) {
+                Some(expected_value) if expected_value == value => {},
+                Some(expected_value) => {
+                    writes.push((*cf, key, Some(expected_value)));
+                    count.changed += 1;
+                },
+                None => {
+                    writes.push((*cf, key, None));
+                    count.removed += 1;
+                },
+            }
+        }
+        for (key, value) in expected {
+            writes.push((*cf, key, Some(value)));
+            count.added += 1;
+        }
+        counts.insert(*cf, count);
+    }
+
+    store.write(writes);
+    counts
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use web3::types::Log;
+
+use crate::store::{OrderStore, EventBatch};
+use crate::processor::{EventMeta, SwapEvent, process_event};
+use crate::migrations::is_empty;
+use crate::evm::EvmDecoder;
+use crate::config::SubstrateConfig;
+use crate::substrate::decode_event;
+
+pub const RECORDING_VERSION: u32 = 2;
+
+/// First line of a recording.
+#[derive(Serialize, Deserialize, Debug)]
+struct RecordingHeader {
+    version: u32,
+}
+
+/// An event as it was received from a chain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RawEvent {
+    Substrate {
+        module: String,
+        variant: String,
+        data: String,
+        extrinsic_index: Option<u32>,
+    },
+    Evm(Log),
+}
+
+/// Chain state read while decoding an event.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainReads {
+    pub block_time: u64,        // milliseconds
+    // Hash of the extrinsic that emitted a Substrate event. EVM logs include their transaction hash.
+    pub extrinsic_hash: [u8; 32],
+    // Value of the event's order once its block has been applied, for events that change an order.
+    pub order_value: Option<u128>,
+}
+
+/// Every other line of a recording is one event, with the chain state read while decoding it, so a recording can be
+/// decoded again without connecting to any chain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedEvent {
+    pub listener: String,
+    pub block_number: u64,
+    pub raw: RawEvent,
+    pub reads: ChainReads,
+}
+
+/// The decoders of the listeners in a recording, found by listener name.
+pub struct ReplayDecoders {
+    pub evm: Vec<(String, EvmDecoder)>,
+    pub substrate: Vec<SubstrateConfig>,
+}
+
+impl ReplayDecoders {
+    /// Decode a raw event the way its listener did.
+    pub fn decode(&self, recorded: &RecordedEvent) -> Option<(EventMeta, SwapEvent)> {
+        match &recorded.raw {
+            RawEvent::Evm(log) => {
+                let decoder = match self.evm.iter().find(|(listener, _decoder)| *listener == recorded.listener) {
+                    Some((_listener, decoder)) => decoder,
+                    None => panic!("No EVM decoder for listener {}.", recorded.listener),
+                };
+                decoder.decode(log, &recorded.reads)
+            },
+            RawEvent::Substrate { module, variant, data, .. } => {
+                if module != "AtomicSwap" { return None };
+                let config = match self.substrate.iter().find(|config| config.name == recorded.listener) {
+                    Some(config) => config,
+                    None => panic!("No Substrate chain configured for listener {}.", recorded.listener),
+                };
+                decode_event(config, recorded.block_number, variant, &hex::decode(data).unwrap(), &recorded.reads)
+            },
+        }
+    }
+}
+
+/// Writes every event the listeners receive to a JSON lines file.
+pub struct Recorder {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Recorder {
+        let mut file = BufWriter::new(File::create(path).unwrap());
+        let header = RecordingHeader {
+            version: RECORDING_VERSION,
+        };
+        writeln!(file, "{}", serde_json::to_string(&header).unwrap()).unwrap();
+        file.flush().unwrap();
+        println!("Recording events to {}.", path);
+        Recorder {
+            file: Mutex::new(file),
+        }
+    }
+
+    pub fn record(&self, event: &RecordedEvent) {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(event).unwrap()).unwrap();
+        // Flushed every event so a recording is complete up to a crash.
+        file.flush().unwrap();
+    }
+}
+
+/// Decode the raw events of a recording and feed them through the event processor into an empty database.
+pub fn replay(store: &dyn OrderStore, path: &str, decoders: &ReplayDecoders) {
+    if !is_empty(store) {
+        panic!("Database is not empty, refusing to replay recording.");
+    }
+
+    let mut lines = BufReader::new(File::open(path).unwrap()).lines();
+    let header: RecordingHeader = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    if header.version != RECORDING_VERSION {
+        panic!("Unsupported recording version: {}", header.version);
+    }
+
+    let mut count = 0;
+    let mut processed = 0;
+    for line in lines {
+        let recorded: RecordedEvent = serde_json::from_str(&line.unwrap()).unwrap();
+        let mut batch = EventBatch::new(store);
+        if let Some((meta, event)) = decoders.decode(&recorded) {
+            process_event(&mut batch, &meta, &event);
+            processed += 1;
+        }
+        batch.set_sync_cursor(&recorded.listener, recorded.block_number);
+        batch.flush();
+        count += 1;
+    }
+    println!("Replayed {} events from {}, {} processed.", count, path, processed);
+}
@@ -4,7 +4,7 @@ use codec::{
     Encode,
 };
 use serde::{Serialize, Deserialize};
-use sp_io::hashing::blake2_128;
+use sp_io::hashing::{blake2_128, keccak_256};
 use strum_macros::Display;
 
 pub struct OrderKey {
@@ -21,6 +21,14 @@ impl OrderKey {
             self.order_id.to_vec(),
         ].concat()
     }
+
+    pub fn unserialize(vec: Vec<u8>) -> OrderKey {
+        OrderKey {
+            chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[4..8].to_vec())),
+            order_id: vector_as_u8_16_array(&vec[8..24].to_vec()),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,6 +44,15 @@ pub struct OrderListKey {
 }
 
 impl OrderListKey {
+    pub fn market(&self) -> MarketKey {
+        MarketKey {
+            sell_chain_id: self.sell_chain_id,
+            sell_asset_id: self.sell_asset_id,
+            buy_chain_id: self.buy_chain_id,
+            buy_asset_id: self.buy_asset_id,
+        }
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         [
             array_to_vec(&self.sell_chain_id.to_be_bytes()),
@@ -61,6 +78,69 @@ impl OrderListKey {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketKey {
+    pub sell_chain_id: u32,
+    pub sell_asset_id: [u8; 8],
+    pub buy_chain_id: u32,
+    pub buy_asset_id: [u8; 8],
+}
+
+impl MarketKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            array_to_vec(&self.sell_chain_id.to_be_bytes()),
+            self.sell_asset_id.to_vec(),
+            array_to_vec(&self.buy_chain_id.to_be_bytes()),
+            self.buy_asset_id.to_vec(),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> MarketKey {
+        MarketKey {
+            sell_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            sell_asset_id: vector_as_u8_8_array(&vec[4..12].to_vec()),
+            buy_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[12..16].to_vec())),
+            buy_asset_id: vector_as_u8_8_array(&vec[16..24].to_vec()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Market {
+    pub order_count: u32,
+    pub total_value: u128,
+}
+
+#[derive(Debug)]
+pub struct MarketPriceKey {
+    pub market: MarketKey,
+    pub price: u128,
+
+    pub sell_adapter_id: u32,
+    pub order_id: [u8; 16],
+}
+
+impl MarketPriceKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            self.market.serialize(),
+            array_to_vec(&self.price.to_be_bytes()),
+            array_to_vec(&self.sell_adapter_id.to_be_bytes()),
+            self.order_id.to_vec(),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> MarketPriceKey {
+        MarketPriceKey {
+            market: MarketKey::unserialize(vec[0..24].to_vec()),
+            price: u128::from_be_bytes(vector_as_u8_16_array(&vec[24..40].to_vec())),
+            sell_adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[40..44].to_vec())),
+            order_id: vector_as_u8_16_array(&vec[44..60].to_vec()),
+        }
+    }
+}
+
 pub struct OrderLockListKey {
     pub chain_id: u32,      // selling chain
     pub adapter_id: u32,    // selling adapter
@@ -113,8 +193,31 @@ pub struct OrderStatic {
 }
 
 impl OrderStatic {
+    /// Order id as derived by the AtomicSwap pallet on Substrate chains.
+    ///
+    /// blake2_128 of the SCALE encoding of seller, chain_id, adapter_id, asset_id, price and foreign_address.
+    /// SCALE integers are little-endian on every host.
     pub fn get_order_id(&self) -> [u8; 16] {
-        blake2_128(&[self.seller.encode(), self.chain_id.encode(), self.adapter_id.encode(), self.asset_id.encode(), self.price.to_ne_bytes().to_vec(), self.foreign_address.encode()].concat())
+        blake2_128(&[self.seller.encode(), self.chain_id.encode(), self.adapter_id.encode(), self.asset_id.encode(), self.price.encode(), self.foreign_address.encode()].concat())
+    }
+
+    /// Order id as derived by the AcuityAtomicSwapSell contract on EVM chains.
+    ///
+    /// First 16 bytes of keccak256(abi.encodePacked(seller, chainIdAdapterIdAssetIdPrice, foreignAddress)).
+    /// The seller address is the last 20 bytes of `seller`.
+    pub fn get_evm_order_id(&self) -> [u8; 16] {
+        let hash = keccak_256(&[self.seller[12..].to_vec(), self.get_chain_id_adapter_id_asset_id_price().to_vec(), self.foreign_address.to_vec()].concat());
+        vector_as_u8_16_array(&hash.to_vec())
+    }
+
+    /// The chainIdAdapterIdAssetIdPrice word of the EVM contracts: big-endian chain_id, adapter_id, asset_id and price.
+    pub fn get_chain_id_adapter_id_asset_id_price(&self) -> [u8; 32] {
+        vector_as_u8_32_array(&[
+            array_to_vec(&self.chain_id.to_be_bytes()),
+            array_to_vec(&self.adapter_id.to_be_bytes()),
+            self.asset_id.to_vec(),
+            array_to_vec(&self.price.to_be_bytes()),
+        ].concat())
     }
 }
 
@@ -125,6 +228,7 @@ pub enum LockState {
     Unlocked,
     TimedOut,
     Invalid,
+    Unverified,
 }
 
 pub struct LockKey {
@@ -141,6 +245,14 @@ impl LockKey {
             self.hashed_secret.to_vec(),
         ].concat()
     }
+
+    pub fn unserialize(vec: Vec<u8>) -> LockKey {
+        LockKey {
+            chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[4..8].to_vec())),
+            hashed_secret: vector_as_u8_32_array(&vec[8..40].to_vec()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -160,6 +272,242 @@ pub struct SellLock {
     pub secret: Option<[u8; 32]>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TradeLeg {
+    pub block_number: u64,
+    pub block_time: u64,        // milliseconds
+    pub tx_hash: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trade {
+    pub sell_chain_id: u32,
+    pub sell_adapter_id: u32,
+    pub sell_asset_id: [u8; 8],
+    pub buy_chain_id: u32,
+    pub buy_adapter_id: u32,
+    pub buy_asset_id: [u8; 8],
+    pub order_id: [u8; 16],
+    pub hashed_secret: [u8; 32],
+    pub price: u128,
+    pub value: u128,
+    pub buyer: [u8; 32],
+    pub seller: [u8; 32],
+    pub sell_unlock: Option<TradeLeg>,    // UnlockSell on the selling chain
+    pub buy_unlock: Option<TradeLeg>,     // UnlockBuy on the buying chain
+}
+
+impl Trade {
+    pub fn is_complete(&self) -> bool {
+        self.sell_unlock.is_some() && self.buy_unlock.is_some()
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        let sell_time = self.sell_unlock.as_ref().map_or(0, |leg| leg.block_time);
+        let buy_time = self.buy_unlock.as_ref().map_or(0, |leg| leg.block_time);
+        sell_time.max(buy_time)
+    }
+
+    pub fn list_key(&self) -> TradeListKey {
+        TradeListKey {
+            sell_chain_id: self.sell_chain_id,
+            sell_asset_id: self.sell_asset_id,
+            buy_chain_id: self.buy_chain_id,
+            buy_asset_id: self.buy_asset_id,
+            timestamp: self.timestamp(),
+            sell_adapter_id: self.sell_adapter_id,
+            hashed_secret: self.hashed_secret,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TradeListKey {
+    pub sell_chain_id: u32,
+    pub sell_asset_id: [u8; 8],
+    pub buy_chain_id: u32,
+    pub buy_asset_id: [u8; 8],
+    pub timestamp: u64,
+
+    pub sell_adapter_id: u32,
+    pub hashed_secret: [u8; 32],
+}
+
+impl TradeListKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            array_to_vec(&self.sell_chain_id.to_be_bytes()),
+            self.sell_asset_id.to_vec(),
+            array_to_vec(&self.buy_chain_id.to_be_bytes()),
+            self.buy_asset_id.to_vec(),
+            array_to_vec(&self.timestamp.to_be_bytes()),
+            array_to_vec(&self.sell_adapter_id.to_be_bytes()),
+            self.hashed_secret.to_vec(),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> TradeListKey {
+        TradeListKey {
+            sell_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            sell_asset_id: vector_as_u8_8_array(&vec[4..12].to_vec()),
+            buy_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[12..16].to_vec())),
+            buy_asset_id: vector_as_u8_8_array(&vec[16..24].to_vec()),
+            timestamp: u64::from_be_bytes(vector_as_u8_8_array(&vec[24..32].to_vec())),
+            sell_adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[32..36].to_vec())),
+            hashed_secret: vector_as_u8_32_array(&vec[36..68].to_vec()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 3] = [CandleInterval::Minute, CandleInterval::Hour, CandleInterval::Day];
+
+    pub fn seconds(&self) -> u32 {
+        match self {
+            CandleInterval::Minute => 60,
+            CandleInterval::Hour => 60 * 60,
+            CandleInterval::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the candle containing timestamp (milliseconds).
+    pub fn start(&self, timestamp: u64) -> u64 {
+        let millis = self.seconds() as u64 * 1000;
+        timestamp - timestamp % millis
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Candle {
+    pub open: u128,
+    pub high: u128,
+    pub low: u128,
+    pub close: u128,
+    pub volume: u128,
+    pub trades: u32,
+    pub open_time: u64,     // timestamp of the trade that set open
+    pub close_time: u64,    // timestamp of the trade that set close
+}
+
+#[derive(Debug)]
+pub struct CandleKey {
+    pub sell_chain_id: u32,
+    pub sell_asset_id: [u8; 8],
+    pub buy_chain_id: u32,
+    pub buy_asset_id: [u8; 8],
+    pub interval: u32,      // seconds
+    pub start: u64,         // milliseconds
+}
+
+impl CandleKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            array_to_vec(&self.sell_chain_id.to_be_bytes()),
+            self.sell_asset_id.to_vec(),
+            array_to_vec(&self.buy_chain_id.to_be_bytes()),
+            self.buy_asset_id.to_vec(),
+            array_to_vec(&self.interval.to_be_bytes()),
+            array_to_vec(&self.start.to_be_bytes()),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> CandleKey {
+        CandleKey {
+            sell_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            sell_asset_id: vector_as_u8_8_array(&vec[4..12].to_vec()),
+            buy_chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[12..16].to_vec())),
+            buy_asset_id: vector_as_u8_8_array(&vec[16..24].to_vec()),
+            interval: u32::from_be_bytes(vector_as_u8_4_array(&vec[24..28].to_vec())),
+            start: u64::from_be_bytes(vector_as_u8_8_array(&vec[28..36].to_vec())),
+        }
+    }
+}
+
+/// An HTLC output on a Bitcoin family chain. The value is the LockKey of its buy lock.
+#[derive(Debug)]
+pub struct HtlcKey {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+impl HtlcKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            array_to_vec(&self.chain_id.to_be_bytes()),
+            array_to_vec(&self.adapter_id.to_be_bytes()),
+            self.txid.to_vec(),
+            array_to_vec(&self.vout.to_be_bytes()),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> HtlcKey {
+        HtlcKey {
+            chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[4..8].to_vec())),
+            txid: vector_as_u8_32_array(&vec[8..40].to_vec()),
+            vout: u32::from_be_bytes(vector_as_u8_4_array(&vec[40..44].to_vec())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AssetKey {
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub asset_id: [u8; 8],
+}
+
+impl AssetKey {
+    pub fn serialize(&self) -> Vec<u8> {
+        [
+            array_to_vec(&self.chain_id.to_be_bytes()),
+            array_to_vec(&self.adapter_id.to_be_bytes()),
+            self.asset_id.to_vec(),
+        ].concat()
+    }
+
+    pub fn unserialize(vec: Vec<u8>) -> AssetKey {
+        AssetKey {
+            chain_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[0..4].to_vec())),
+            adapter_id: u32::from_be_bytes(vector_as_u8_4_array(&vec[4..8].to_vec())),
+            asset_id: vector_as_u8_8_array(&vec[8..16].to_vec()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Wire encoding of websocket messages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+/// How an order in a market's order book changed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderBookChange {
+    Added,
+    ValueChanged,
+    Removed,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum RequestMessage {
@@ -174,6 +522,81 @@ pub enum RequestMessage {
         sell_adapter_id: u32,
         order_id: String,
     },
+    GetTrades {
+        sell_chain_id: u32,
+        sell_asset_id: String,
+        buy_chain_id: u32,
+        buy_asset_id: String,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: Option<u32>,
+    },
+    GetCandles {
+        sell_chain_id: u32,
+        sell_asset_id: String,
+        buy_chain_id: u32,
+        buy_asset_id: String,
+        interval: CandleInterval,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: Option<u32>,
+    },
+    SubscribeCandles {
+        sell_chain_id: u32,
+        sell_asset_id: String,
+        buy_chain_id: u32,
+        buy_asset_id: String,
+        interval: CandleInterval,
+    },
+    UnsubscribeCandles {
+        sell_chain_id: u32,
+        sell_asset_id: String,
+        buy_chain_id: u32,
+        buy_asset_id: String,
+        interval: CandleInterval,
+    },
+    ListAssets,
+    ListMarkets,
+    RegisterAsset {
+        chain_id: u32,
+        adapter_id: u32,
+        asset_id: String,
+        symbol: String,
+        decimals: u8,
+    },
+    SetSchemaVersion {
+        version: u32,
+    },
+    SetEncoding {
+        encoding: Encoding,
+    },
+    /// Published by the processor when an order in a market changes. Not accepted from clients.
+    #[serde(skip_deserializing)]
+    OrderBookDelta {
+        market: MarketKey,
+        sequence: u64,
+        change: OrderBookChange,
+        sell_adapter_id: u32,
+        order_id: [u8; 16],
+        value: u128,
+    },
+}
+
+/// Format an integer amount in base units as a decimal string, e.g. 1500000 with 6 decimals is "1.5".
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
 }
 
 pub fn array_to_vec(arr: &[u8]) -> Vec<u8> {
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use serde::{Serialize, Deserialize};
+use serde::de::{DeserializeOwned, IgnoredAny};
+
+use crate::shared::*;
+use crate::store::{OrderStore, COLUMN_FAMILIES};
+use crate::migrations::{SCHEMA_VERSION, is_empty};
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// First line of a snapshot.
+#[derive(Serialize, Deserialize, Debug)]
+struct SnapshotHeader {
+    version: u32,
+    schema_version: u32,
+    sync_cursors: BTreeMap<String, u64>,
+}
+
+/// Every other line of a snapshot is one database entry.
+#[derive(Serialize, Deserialize, Debug)]
+struct SnapshotRecord<T> {
+    cf: String,
+    key: String,
+    value: T,
+}
+
+// Values go straight between bincode and JSON text: serde_json::Value cannot hold amounts above u64::MAX.
+fn decode<T: Serialize + DeserializeOwned>(value: &[u8]) -> String {
+    let decoded: T = bincode::deserialize(value).unwrap();
+    serde_json::to_string(&decoded).unwrap()
+}
+
+fn encode<T: Serialize + DeserializeOwned>(line: &str) -> Vec<u8> {
+    let record: SnapshotRecord<T> = serde_json::from_str(line).unwrap();
+    bincode::serialize(&record.value).unwrap()
+}
+
+// Values stored with bincode are written decoded, everything else as hex.
+pub fn decode_value(cf: &str, value: &[u8]) -> String {
+    match cf {
+        "order_static" => decode::<OrderStatic>(value),
+        "buy_lock" => decode::<BuyLock>(value),
+        "sell_lock" => decode::<SellLock>(value),
+        "trade" => decode::<Trade>(value),
+        "pending_unlock" => decode::<TradeLeg>(value),
+        "candle" => decode::<Candle>(value),
+        "asset" => decode::<Asset>(value),
+        "market" => decode::<Market>(value),
+        _ => serde_json::to_string(&hex::encode(value)).unwrap(),
+    }
+}
+
+// Parses the value of a snapshot line.
+fn encode_value(cf: &str, line: &str) -> Vec<u8> {
+    match cf {
+        "order_static" => encode::<OrderStatic>(line),
+        "buy_lock" => encode::<BuyLock>(line),
+        "sell_lock" => encode::<SellLock>(line),
+        "trade" => encode::<Trade>(line),
+        "pending_unlock" => encode::<TradeLeg>(line),
+        "candle" => encode::<Candle>(line),
+        "asset" => encode::<Asset>(line),
+        "market" => encode::<Market>(line),
+        _ => {
+            let record: SnapshotRecord<String> = serde_json::from_str(line).unwrap();
+            hex::decode(&record.value).unwrap()
+        },
+    }
+}
+
+/// Write every column family and the sync cursors to a JSON lines file.
+pub fn export_snapshot(store: &dyn OrderStore, path: &str) {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+
+    let header = SnapshotHeader {
+        version: SNAPSHOT_VERSION,
+        schema_version: SCHEMA_VERSION,
+        sync_cursors: store.list_sync_cursors(),
+    };
+    writeln!(file, "{}", serde_json::to_string(&header).unwrap()).unwrap();
+
+    let mut count = 0;
+    for cf in COLUMN_FAMILIES.iter() {
+        // Sync cursors and the schema version are in the header.
+        if *cf == "sync_cursor" || *cf == "metadata" { continue };
+        for (key, value) in store.iter(*cf) {
+            writeln!(file, "{{\"cf\":\"{}\",\"key\":\"{}\",\"value\":{}}}", cf, hex::encode(&key), decode_value(cf, &value)).unwrap();
+            count += 1;
+        }
+    }
+    file.flush().unwrap();
+    println!("Exported {} entries to {}.", count, path);
+}
+
+/// Load a snapshot into an empty database.
+pub fn import_snapshot(store: &dyn OrderStore, path: &str) {
+    if !is_empty(store) {
+        panic!("Database is not empty, refusing to import snapshot.");
+    }
+
+    let mut lines = BufReader::new(File::open(path).unwrap()).lines();
+    let header: SnapshotHeader = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    if header.version != SNAPSHOT_VERSION {
+        panic!("Unsupported snapshot version: {}", header.version);
+    }
+    if header.schema_version != SCHEMA_VERSION {
+        panic!("Snapshot has schema version {}, this indexer uses {}.", header.schema_version, SCHEMA_VERSION);
+    }
+
+    let mut count = 0;
+    for line in lines {
+        let line = line.unwrap();
+        let record: SnapshotRecord<IgnoredAny> = serde_json::from_str(&line).unwrap();
+        let cf = match COLUMN_FAMILIES.iter().find(|cf| **cf == record.cf) {
+            Some(cf) => *cf,
+            None => panic!("Unknown column family in snapshot: {}", record.cf),
+        };
+        store.put(cf, &hex::decode(&record.key).unwrap(), &encode_value(cf, &line));
+        count += 1;
+    }
+
+    for (listener, block_number) in header.sync_cursors.iter() {
+        store.set_sync_cursor(listener, *block_number);
+    }
+    println!("Imported {} entries from {}, sync cursors: {:?}", count, path, header.sync_cursors);
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use tokio::sync::broadcast::Sender;
+
+use crate::shared::*;
+
+pub const COLUMN_FAMILIES: [&str; 18] = [
+    "order_static",
+    "order_value",
+    "order_list",
+    "order_lock_list",
+    "buy_lock",
+    "sell_lock",
+    "trade",
+    "trade_list",
+    "candle",
+    "asset",
+    "market",
+    "market_price",
+    "sync_cursor",
+    "metadata",
+    "htlc",
+    "market_sequence",
+    "pending_unlock",
+    "buy_lock_secret",
+];
+
+/// A write to a column family. A value of None deletes the key.
+pub type CfWrite = (&'static str, Vec<u8>, Option<Vec<u8>>);
+
+fn decode<T: serde::de::DeserializeOwned>(value: Option<Vec<u8>>) -> Option<T> {
+    value.map(|value| bincode::deserialize(&value).unwrap())
+}
+
+/// Storage for everything the indexer knows. Backends implement raw column family access, the rest of the crate uses the typed methods.
+pub trait OrderStore: Send + Sync {
+    fn get(&self, cf: &'static str, key: &[u8]) -> Option<Vec<u8>>;
+    fn put(&self, cf: &'static str, key: &[u8], value: &[u8]);
+    fn delete(&self, cf: &'static str, key: &[u8]);
+    /// Entries of a column family in key order, starting at `from`.
+    fn iter_from<'a>(&'a self, cf: &'static str, from: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+    /// Apply all writes atomically.
+    fn write(&self, writes: Vec<CfWrite>);
+    /// Held by an EventBatch from numbering its order book deltas until they have been sent, so every market's
+    /// deltas go out once each and in order, whichever listener committed them.
+    fn commit_lock(&self) -> &Mutex<()>;
+
+    fn iter<'a>(&'a self, cf: &'static str) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        self.iter_from(cf, &[])
+    }
+
+    fn get_order(&self, key: &OrderKey) -> Option<OrderStatic> {
+        decode(self.get("order_static", &key.serialize()))
+    }
+
+    fn put_order(&self, key: &OrderKey, order: &OrderStatic) {
+        self.put("order_static", &key.serialize(), &bincode::serialize(order).unwrap());
+    }
+
+    fn delete_order(&self, key: &OrderKey) {
+        self.delete("order_static", &key.serialize());
+    }
+
+    fn get_order_value(&self, key: &OrderKey) -> Option<u128> {
+        self.get("order_value", &key.serialize()).map(|value| u128::from_be_bytes(vector_as_u8_16_array(&value)))
+    }
+
+    fn put_order_value(&self, key: &OrderKey, value: u128) {
+        self.put("order_value", &key.serialize(), &value.to_be_bytes());
+    }
+
+    fn delete_order_value(&self, key: &OrderKey) {
+        self.delete("order_value", &key.serialize());
+    }
+
+    fn put_order_list(&self, key: &OrderListKey) {
+        self.put("order_list", &key.serialize(), &key.order_id);
+    }
+
+    fn delete_order_list(&self, key: &OrderListKey) {
+        self.delete("order_list", &key.serialize());
+    }
+
+    /// Orders in a market, lowest value first.
+    fn iter_market(&self, market: &MarketKey) -> Vec<OrderListKey> {
+        let start_key = OrderListKey {
+            sell_chain_id: market.sell_chain_id,
+            sell_asset_id: market.sell_asset_id,
+            buy_chain_id: market.buy_chain_id,
+            buy_asset_id: market.buy_asset_id,
+            value: u128::default(),
+            sell_adapter_id: u32::default(),
+            order_id: <[u8; 16]>::default(),
+        };
+        self.iter_from("order_list", &start_key.serialize())
+            .map(|(key, _value)| OrderListKey::unserialize(key))
+            .take_while(|key| key.market() == *market)
+            .collect()
+    }
+
+    fn put_order_lock(&self, key: &OrderLockListKey) {
+        self.put("order_lock_list", &key.serialize(), &key.hashed_secret);
+    }
+
+    /// Locks on an order, lowest value first.
+    fn iter_order_locks(&self, order_key: &OrderKey) -> Vec<OrderLockListKey> {
+        let start_key = OrderLockListKey {
+            chain_id: order_key.chain_id,
+            adapter_id: order_key.adapter_id,
+            order_id: order_key.order_id,
+            value: u128::default(),
+            hashed_secret: <[u8; 32]>::default(),
+        };
+        self.iter_from("order_lock_list", &start_key.serialize())
+            .map(|(key, _value)| OrderLockListKey::unserialize(key))
+            .take_while(|key| key.chain_id == order_key.chain_id && key.adapter_id == order_key.adapter_id && key.order_id == order_key.order_id)
+            .collect()
+    }
+
+    fn get_buy_lock(&self, key: &LockKey) -> Option<BuyLock> {
+        decode(self.get("buy_lock", &key.serialize()))
+    }
+
+    fn put_buy_lock(&self, key: &LockKey, buy_lock: &BuyLock) {
+        self.put("buy_lock", &key.serialize(), &bincode::serialize(buy_lock).unwrap());
+    }
+
+    /// The key of a buy lock from the chain it was made on and its hashed secret. Buy side events on that chain only
+    /// carry the hashed secret, not the chain of the order.
+    fn get_buy_lock_key(&self, key: &LockKey) -> Option<LockKey> {
+        self.get("buy_lock_secret", &key.serialize()).map(LockKey::unserialize)
+    }
+
+    fn put_buy_lock_key(&self, key: &LockKey, lock_key: &LockKey) {
+        self.put("buy_lock_secret", &key.serialize(), &lock_key.serialize());
+    }
+
+    fn get_sell_lock(&self, key: &LockKey) -> Option<SellLock> {
+        decode(self.get("sell_lock", &key.serialize()))
+    }
+
+    fn put_sell_lock(&self, key: &LockKey, sell_lock: &SellLock) {
+        self.put("sell_lock", &key.serialize(), &bincode::serialize(sell_lock).unwrap());
+    }
+
+    fn get_trade(&self, key: &LockKey) -> Option<Trade> {
+        decode(self.get("trade", &key.serialize()))
+    }
+
+    fn put_trade(&self, key: &LockKey, trade: &Trade) {
+        self.put("trade", &key.serialize(), &bincode::serialize(trade).unwrap());
+    }
+
+    /// A sell side unlock processed before the buy lock it completes was indexed.
+    fn get_pending_unlock(&self, key: &LockKey) -> Option<TradeLeg> {
+        decode(self.get("pending_unlock", &key.serialize()))
+    }
+
+    fn put_pending_unlock(&self, key: &LockKey, leg: &TradeLeg) {
+        self.put("pending_unlock", &key.serialize(), &bincode::serialize(leg).unwrap());
+    }
+
+    fn delete_pending_unlock(&self, key: &LockKey) {
+        self.delete("pending_unlock", &key.serialize());
+    }
+
+    fn put_trade_list(&self, key: &TradeListKey) {
+        self.put("trade_list", &key.serialize(), &key.hashed_secret);
+    }
+
+    fn get_candle(&self, key: &CandleKey) -> Option<Candle> {
+        decode(self.get("candle", &key.serialize()))
+    }
+
+    fn put_candle(&self, key: &CandleKey, candle: &Candle) {
+        self.put("candle", &key.serialize(), &bincode::serialize(candle).unwrap());
+    }
+
+    fn get_asset(&self, key: &AssetKey) -> Option<Asset> {
+        decode(self.get("asset", &key.serialize()))
+    }
+
+    fn put_asset(&self, key: &AssetKey, asset: &Asset) {
+        self.put("asset", &key.serialize(), &bincode::serialize(asset).unwrap());
+    }
+
+    fn get_market(&self, key: &MarketKey) -> Option<Market> {
+        decode(self.get("market", &key.serialize()))
+    }
+
+    fn put_market(&self, key: &MarketKey, market: &Market) {
+        self.put("market", &key.serialize(), &bincode::serialize(market).unwrap());
+    }
+
+    fn delete_market(&self, key: &MarketKey) {
+        self.delete("market", &key.serialize());
+    }
+
+    fn put_market_price(&self, key: &MarketPriceKey) {
+        self.put("market_price", &key.serialize(), &key.order_id);
+    }
+
+    fn delete_market_price(&self, key: &MarketPriceKey) {
+        self.delete("market_price", &key.serialize());
+    }
+
+    /// Sequence number of the last order book delta published for a market, 0 if there has been none.
+    fn get_market_sequence(&self, key: &MarketKey) -> u64 {
+        self.get("market_sequence", &key.serialize()).map(|value| u64::from_be_bytes(vector_as_u8_8_array(&value))).unwrap_or(0)
+    }
+
+    fn put_market_sequence(&self, key: &MarketKey, sequence: u64) {
+        self.put("market_sequence", &key.serialize(), &sequence.to_be_bytes());
+    }
+
+    fn get_htlc(&self, key: &HtlcKey) -> Option<LockKey> {
+        self.get("htlc", &key.serialize()).map(LockKey::unserialize)
+    }
+
+    fn put_htlc(&self, key: &HtlcKey, lock_key: &LockKey) {
+        self.put("htlc", &key.serialize(), &lock_key.serialize());
+    }
+
+    /// The last block a listener has processed.
+    fn get_sync_cursor(&self, listener: &str) -> Option<u64> {
+        self.get("sync_cursor", listener.as_bytes()).map(|value| u64::from_be_bytes(vector_as_u8_8_array(&value)))
+    }
+
+    fn set_sync_cursor(&self, listener: &str, block_number: u64) {
+        self.put("sync_cursor", listener.as_bytes(), &block_number.to_be_bytes());
+    }
+
+    /// Sync cursors of every listener that has processed a block.
+    fn list_sync_cursors(&self) -> BTreeMap<String, u64> {
+        self.iter("sync_cursor")
+            .map(|(key, value)| (String::from_utf8(key).unwrap(), u64::from_be_bytes(vector_as_u8_8_array(&value))))
+            .collect()
+    }
+
+    fn get_schema_version(&self) -> Option<u32> {
+        self.get("metadata", b"schema_version").map(|value| u32::from_be_bytes(vector_as_u8_4_array(&value)))
+    }
+
+    fn set_schema_version(&self, version: u32) {
+        self.put("metadata", b"schema_version", &version.to_be_bytes());
+    }
+}
+
+/// Store that only lives in memory, for tests and embedders that do not need persistence.
+#[derive(Default)]
+pub struct MemoryStore {
+    cfs: Mutex<HashMap<&'static str, BTreeMap<Vec<u8>, Vec<u8>>>>,
+    commit_lock: Mutex<()>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl OrderStore for MemoryStore {
+    fn get(&self, cf: &'static str, key: &[u8]) -> Option<Vec<u8>> {
+        self.cfs.lock().unwrap().get(cf).and_then(|entries| entries.get(key).cloned())
+    }
+
+    fn put(&self, cf: &'static str, key: &[u8], value: &[u8]) {
+        self.cfs.lock().unwrap().entry(cf).or_default().insert(key.to_vec(), value.to_vec());
+    }
+
+    fn delete(&self, cf: &'static str, key: &[u8]) {
+        if let Some(entries) = self.cfs.lock().unwrap().get_mut(cf) {
+            entries.remove(key);
+        }
+    }
+
+    fn iter_from<'a>(&'a self, cf: &'static str, from: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a> {
+        // Copy the range out so the lock is not held while iterating.
+        let entries = match self.cfs.lock().unwrap().get(cf) {
+            Some(entries) => entries.range(from.to_vec()..).map(|(key, value)| (key.clone(), value.clone())).collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        Box::new(entries.into_iter())
+    }
+
+    fn write(&self, writes: Vec<CfWrite>) {
+        let mut cfs = self.cfs.lock().unwrap();
+        for (cf, key, value) in writes {
+            let entries = cfs.entry(cf).or_default();
+            match value {
+                Some(value) => { entries.insert(key, value); },
+                None => { entries.remove(&key); },
+            }
+        }
+    }
+
+    fn commit_lock(&self) -> &Mutex<()> {
+        &self.commit_lock
+    }
+}
+
+
+/// All writes caused by one event, committed atomically together with the sync cursor.
+/// Reads through the batch see its own pending writes, iteration only sees what has been committed.
+/// Notifications are only sent once the batch has been committed.
+pub struct EventBatch<'a> {
+    store: &'a dyn OrderStore,
+    pending: Mutex<(HashMap<(&'static str, Vec<u8>), Option<Vec<u8>>>, Vec<CfWrite>)>,
+    notifications: Vec<RequestMessage>,
+}
+
+impl<'a> EventBatch<'a> {
+    pub fn new(store: &'a dyn OrderStore) -> EventBatch<'a> {
+        EventBatch {
+            store: store,
+            pending: Mutex::new((HashMap::new(), Vec::new())),
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Queue a notification for websocket clients.
+    pub fn notify(&mut self, msg: RequestMessage) {
+        self.notifications.push(msg);
+    }
+
+    /// Write the batch under the store's commit lock and hand the queued notifications to `send` before releasing
+    /// it. Order book deltas are numbered here, so batches committed concurrently never reuse a sequence. Nothing is
+    /// written if `check` does not hold for the committed state once the lock is held.
+    fn commit_with(mut self, check: impl FnOnce(&dyn OrderStore) -> bool, mut send: impl FnMut(RequestMessage)) -> bool {
+        let store = self.store;
+        let _guard = store.commit_lock().lock().unwrap();
+        if !check(store) {
+            return false;
+        }
+        let mut notifications = std::mem::take(&mut self.notifications);
+        for msg in notifications.iter_mut() {
+            if let RequestMessage::OrderBookDelta { market, sequence, .. } = msg {
+                *sequence = self.get_market_sequence(market) + 1;
+                self.put_market_sequence(market, *sequence);
+            }
+        }
+        let (_overlay, writes) = self.pending.into_inner().unwrap();
+        store.write(writes);
+        for msg in notifications {
+            send(msg);
+        }
+        true
+    }
+
+    /// Write the batch, returning the queued notifications.
+    pub fn flush(self) -> Vec<RequestMessage> {
+        let mut notifications = Vec::new();
+        self.commit_with(|_store| true, |msg| notifications.push(msg));
+        notifications
+    }
+
+    /// Write the batch, then send the queued notifications.
+    pub fn commit(self, tx: &Sender<RequestMessage>) {
+        self.commit_with(|_store| true, |msg| { tx.send(msg).unwrap(); });
+    }
+
+    /// Write the batch and send the queued notifications only if `check` holds for the committed state, so no batch
+    /// committed since the batch was built is overwritten. Returns whether the batch was written.
+    pub fn commit_if(self, tx: &Sender<RequestMessage>, check: impl FnOnce(&dyn OrderStore) -> bool) -> bool {
+        self.commit_with(check, |msg| { tx.send(msg).unwrap(); })
+    }
+}
+
+impl<'a> OrderStore for EventBatch<'a> {
+    fn get(&self, cf: &'static str, key: &[u8]) -> Option<Vec<u8>> {
+        match self.pending.lock().unwrap().0.get(&(cf, key.to_vec())) {
+            Some(value) => value.clone(),
+            None => self.store.get(cf, key),
+        }
+    }
+
+    fn put(&self, cf: &'static str, key: &[u8], value: &[u8]) {
+        self.write(vec![(cf, key.to_vec(), Some(value.to_vec()))]);
+    }
+
+    fn delete(&self, cf: &'static str, key: &[u8]) {
+        self.write(vec![(cf, key.to_vec(), None)]);
+    }
+
+    fn iter_from<'b>(&'b self, cf: &'static str, from: &[u8]) -> Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'b> {
+        self.store.iter_from(cf, from)
+    }
+
+    fn commit_lock(&self) -> &Mutex<()> {
+        self.store.commit_lock()
+    }
+
+    fn write(&self, writes: Vec<CfWrite>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (cf, key, value) in writes {
+            pending.0.insert((cf, key.clone()), value.clone());
+            pending.1.push((cf, key, value));
+        }
+    }
+}
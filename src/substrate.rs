@@ -0,0 +1,847 @@
+use substrate_subxt::{
+    balances::{
+        AccountData,
+        Balances,
+        BalancesEventTypeRegistry,
+    },
+    session::{
+        Session,
+        SessionEventTypeRegistry,
+    },
+    staking::{
+        Staking,
+        StakingEventTypeRegistry,
+    },
+    sudo::{
+        Sudo,
+        SudoEventTypeRegistry,
+    },
+    system::{
+        System,
+        SystemEventTypeRegistry,
+    },
+    ClientBuilder, Client,
+    sp_runtime::traits::{
+        AtLeast32Bit,
+        MaybeSerialize,
+        Member,
+    },
+    EventTypeRegistry,
+    extrinsic::{
+        DefaultExtra,
+    },
+    BasicSessionKeys,
+    Runtime,
+};
+use sp_runtime::{
+    generic::Header,
+    traits::{
+        BlakeTwo256,
+        IdentifyAccount,
+        Verify,
+    },
+    MultiSignature,
+    OpaqueExtrinsic,
+};
+use sp_io::hashing::{blake2_128, blake2_256, keccak_256};
+use sp_core::storage::{StorageChangeSet, StorageData, StorageKey};
+use sp_core::twox_128;
+use codec::{
+    Codec,
+    Decode,
+    Encode,
+};
+use proc_macro::*;
+use serde::{Serialize, Deserialize};
+use std::fmt::Debug;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::broadcast::Sender;
+use scale_info::{MetaType, TypeInfo};
+use frame_metadata::RuntimeMetadataPrefixed;
+
+use crate::shared::*;
+use crate::processor::{EventMeta, SwapEvent, process_event};
+use crate::auditor::{AuditScope, ChainState, report_drift};
+use crate::store::{OrderStore, EventBatch};
+use crate::recorder::{Recorder, RecordedEvent, RawEvent, ChainReads};
+use crate::config::SubstrateConfig;
+use crate::substrate_events::EventDecoder;
+
+/// Runtime of any chain with the AtomicSwap pallet. Types that differ between chains are registered from the config.
+#[derive(Debug, Clone, Eq, PartialEq, TypeInfo, Serialize, Deserialize)]
+pub struct SubstrateRuntime;
+
+impl Staking for SubstrateRuntime {}
+
+impl Runtime for SubstrateRuntime {
+    type Signature = MultiSignature;
+    type Extra = DefaultExtra<Self>;
+
+    fn register_type_sizes(event_type_registry: &mut EventTypeRegistry<Self>) {
+        event_type_registry.with_system();
+        event_type_registry.with_balances();
+        event_type_registry.with_session();
+        event_type_registry.with_staking();
+        event_type_registry.with_sudo();
+        substrate_subxt::register_default_type_sizes(event_type_registry);
+    }
+}
+
+impl System for SubstrateRuntime {
+    type Index = u32;
+    type BlockNumber = u32;
+    type Hash = sp_core::H256;
+    type Hashing = BlakeTwo256;
+    type AccountId = <<MultiSignature as Verify>::Signer as IdentifyAccount>::AccountId;
+    type Address = sp_runtime::MultiAddress<Self::AccountId, u32>;
+    type Header = Header<Self::BlockNumber, BlakeTwo256>;
+    type Extrinsic = OpaqueExtrinsic;
+    type AccountData = AccountData<<Self as Balances>::Balance>;
+}
+
+impl Balances for SubstrateRuntime {
+    type Balance = u128;
+}
+
+impl Session for SubstrateRuntime {
+    type ValidatorId = <Self as System>::AccountId;
+    type Keys = BasicSessionKeys;
+}
+
+impl Sudo for SubstrateRuntime {}
+
+impl AtomicSwap for SubstrateRuntime {
+    type Balance = u128;
+    type Moment = u64;
+}
+
+
+
+#[module]
+pub trait AtomicSwap: System {
+    type Balance: Member
+        + AtLeast32Bit
+        + Codec
+        + Default
+        + Copy
+        + MaybeSerialize
+        + Debug
+        + From<<Self as System>::BlockNumber>;
+    type Moment: Member
+        + AtLeast32Bit
+        + Codec
+        + Default
+        + Copy
+        + MaybeSerialize
+        + Debug
+        + From<<Self as System>::BlockNumber>;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RuntimeVersion {
+    spec_version: u32,
+}
+
+struct SubstrateApi {
+    client: Client::<SubstrateRuntime>,
+}
+
+impl SubstrateApi {
+/*
+    async fn get_storage_data(
+        &self,
+        module_name: &str,
+        storage_name: &str,
+        header_hash: H256,
+    ) -> Result<StorageData, &str> {
+        let mut storage_key = twox_128(module_name.as_bytes()).to_vec();
+        storage_key.extend(twox_128(storage_name.as_bytes()).to_vec());
+
+        let keys = vec![StorageKey(storage_key)];
+
+        let change_sets = self
+            .client
+            .query_storage(keys, header_hash, Some(header_hash))
+            .await.unwrap();
+        for change_set in change_sets {
+            for (_key, data) in change_set.changes {
+                if let Some(data) = data {
+                    return Ok(data);
+                }
+            }
+        }
+
+        Err("Data not found.")
+    }
+*/
+    fn storage_map_key(module_name: &str, storage_name: &str, key: &[u8; 16]) -> StorageKey {
+        let mut storage_key = twox_128(module_name.as_bytes()).to_vec();
+        storage_key.extend(twox_128(storage_name.as_bytes()).to_vec());
+        storage_key.extend(blake2_128(&key.encode()).to_vec());
+        storage_key.extend(key.encode());
+        StorageKey(storage_key)
+    }
+
+    async fn get_block_hash(&self, block_number: u64) -> Result<sp_core::H256, String> {
+        let params = vec![serde_json::to_value(block_number).unwrap()];
+        let block_hash: Option<sp_core::H256> = self
+        .client
+        .rpc_client()
+        .request("chain_getBlockHash", &params)
+        .await
+        .map_err(|err| format!("chain_getBlockHash failed: {}", err))?;
+        block_hash.ok_or(format!("block {} not found", block_number))
+    }
+
+    async fn get_head_number(&self) -> Result<u64, String> {
+        let header = self
+        .client
+        .header(None::<sp_core::H256>)
+        .await
+        .map_err(|err| format!("chain_getHeader failed: {}", err))?
+        .ok_or("no head block".to_string())?;
+        Ok(header.number.into())
+    }
+
+    /// System.Events of a block, for blocks that did not come from the event subscription.
+    async fn get_events(&self, block_hash: sp_core::H256) -> Result<Option<StorageData>, String> {
+        let mut storage_key = twox_128(b"System").to_vec();
+        storage_key.extend(twox_128(b"Events").to_vec());
+        let params = vec![
+            serde_json::to_value(StorageKey(storage_key)).unwrap(),
+            serde_json::to_value(block_hash).unwrap(),
+        ];
+        self
+        .client
+        .rpc_client()
+        .request("state_getStorage", &params)
+        .await
+        .map_err(|err| format!("state_getStorage failed: {}", err))
+    }
+
+    async fn get_metadata(&self, block_hash: Option<sp_core::H256>) -> RuntimeMetadataPrefixed {
+        let params = match block_hash {
+            Some(block_hash) => vec![serde_json::to_value(block_hash).unwrap()],
+            None => vec![],
+        };
+        let metadata: sp_core::Bytes = self
+        .client
+        .rpc_client()
+        .request("state_getMetadata", &params)
+        .await.unwrap();
+        RuntimeMetadataPrefixed::decode(&mut &metadata[..]).unwrap()
+    }
+
+    async fn get_spec_version(&self, block_hash: Option<sp_core::H256>) -> u32 {
+        let params = match block_hash {
+            Some(block_hash) => vec![serde_json::to_value(block_hash).unwrap()],
+            None => vec![],
+        };
+        let version: RuntimeVersion = self
+        .client
+        .rpc_client()
+        .request("state_getRuntimeVersion", &params)
+        .await.unwrap();
+        version.spec_version
+    }
+
+    /// Values of several orders at a block, read with a single query. `state_queryStorageAt` is one of the safe RPC
+    /// methods, so this works against public nodes.
+    async fn get_order_values(&self, order_ids: &BTreeSet<[u8; 16]>, block_hash: sp_core::H256) -> Result<BTreeMap<[u8; 16], u128>, String> {
+        let mut values = order_ids.iter().map(|order_id| (*order_id, 0)).collect::<BTreeMap<_, _>>();
+        if order_ids.is_empty() { return Ok(values) };
+        let keys = order_ids.iter()
+            .map(|order_id| (Self::storage_map_key("AtomicSwap", "AcuityOrderIdValues", order_id).0, *order_id))
+            .collect::<BTreeMap<_, _>>();
+
+        let params = vec![
+            serde_json::to_value(keys.keys().cloned().map(StorageKey).collect::<Vec<_>>()).unwrap(),
+            serde_json::to_value(block_hash).unwrap(),
+        ];
+        let change_sets: Vec<StorageChangeSet<sp_core::H256>> = self
+        .client
+        .rpc_client()
+        .request("state_queryStorageAt", &params)
+        .await
+        .map_err(|err| format!("state_queryStorageAt failed: {}", err))?;
+
+        for change_set in change_sets {
+            for (key, data) in change_set.changes {
+                if let (Some(order_id), Some(data)) = (keys.get(&key.0), data) {
+                    let value = u128::decode(&mut &data.0[..]).map_err(|err| format!("Invalid order value: {}", err))?;
+                    values.insert(*order_id, value);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Read the chain state the AtomicSwap events of a block are decoded with. The block and its time are only fetched
+    /// when it has events to decode.
+    async fn read_block(&self, block_hash: sp_core::H256, has_events: bool, order_ids: &BTreeSet<[u8; 16]>) -> Result<BlockReads, String> {
+        if !has_events {
+            return Ok(BlockReads::default());
+        }
+        let block = self
+        .client
+        .block(Some(block_hash))
+        .await
+        .map_err(|err| format!("chain_getBlock failed: {}", err))?
+        .ok_or(format!("block {:?} not found", block_hash))?;
+
+        let mut storage_key = twox_128(b"Timestamp").to_vec();
+        storage_key.extend(twox_128(b"Now").to_vec());
+
+        let block_time: Option<u64> = self
+        .client
+        .fetch_unhashed(StorageKey(storage_key), Some(block_hash))
+        .await
+        .map_err(|err| format!("reading Timestamp.Now failed: {}", err))?;
+
+        Ok(BlockReads {
+            block_time: block_time.unwrap_or_default(),
+            extrinsic_hashes: block.block.extrinsics.iter().map(|extrinsic| blake2_256(&extrinsic.encode())).collect(),
+            order_values: self.get_order_values(order_ids, block_hash).await?,
+        })
+    }
+}
+
+/// Chain state read once per block for all of its events.
+#[derive(Default)]
+struct BlockReads {
+    block_time: u64,
+    extrinsic_hashes: Vec<[u8; 32]>,
+    order_values: BTreeMap<[u8; 16], u128>,
+}
+
+impl BlockReads {
+    /// The chain state an event is decoded with.
+    fn event_reads(&self, extrinsic_index: Option<u32>, order_id: Option<[u8; 16]>) -> ChainReads {
+        ChainReads {
+            block_time: self.block_time,
+            extrinsic_hash: extrinsic_index
+                .and_then(|index| self.extrinsic_hashes.get(index as usize).cloned())
+                .unwrap_or_default(),
+            order_value: order_id.map(|order_id| self.order_values.get(&order_id).cloned().unwrap_or(0)),
+        }
+    }
+}
+
+
+/// AddToOrder event.
+#[derive(Debug, Decode, Eq, Event, PartialEq, TypeInfo)]
+pub struct AddToOrderEvent<T: AtomicSwap> {
+    pub seller: <T as System>::AccountId,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub asset_id: [u8; 8],
+    pub price: u128,
+    pub foreign_address: [u8; 32],
+    pub value: T::Balance,
+}
+
+/// RemoveFromOrder event.
+#[derive(Debug, Decode, Eq, Event, PartialEq, TypeInfo)]
+pub struct RemoveFromOrderEvent<T: AtomicSwap> {
+    pub seller: <T as System>::AccountId,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub asset_id: [u8; 8],
+    pub price: u128,
+    pub foreign_address: [u8; 32],
+    pub value: T::Balance,
+}
+
+/// LockSell event.
+#[derive(Debug, Decode, Eq, Event, PartialEq, TypeInfo)]
+pub struct LockSellEvent<T: AtomicSwap> {
+    pub order_id: [u8; 16],
+    pub hashed_secret: [u8; 32],
+    pub timeout: T::Moment,
+    pub value: T::Balance,
+}
+
+/// UnlockSell event.
+#[derive(Debug, Decode, Eq, Event, PartialEq, TypeInfo)]
+pub struct UnlockSellEvent<T: AtomicSwap> {
+    pub order_id: [u8; 16],
+    pub secret: [u8; 32],
+    pub buyer: <T as System>::AccountId,
+}
+
+/// TimeoutSell event.
+#[derive(Debug, Decode, Eq, PartialEq, TypeInfo)]
+pub struct TimeoutSellEvent {
+    pub order_id: [u8; 16],
+    pub hashed_secret: [u8; 32],
+}
+
+/// LockBuy event.
+#[derive(Debug, Decode, Eq, Event, PartialEq, TypeInfo)]
+pub struct LockBuyEvent<T: AtomicSwap> {
+    pub buyer: <T as System>::AccountId,
+    pub seller: <T as System>::AccountId,
+    pub hashed_secret: [u8; 32],
+    pub timeout: T::Moment,
+    pub value: T::Balance,
+    pub chain_id: u32,
+    pub adapter_id: u32,
+    pub order_id: [u8; 16],
+    pub foreign_address: [u8; 32],
+}
+
+/// UnlockBuy event.
+#[derive(Debug, Decode, Eq, PartialEq, TypeInfo)]
+pub struct UnlockBuyEvent<T: AtomicSwap> {
+    pub buyer: <T as System>::AccountId,
+    pub hashed_secret: [u8; 32],
+}
+
+/// TimeoutBuy event.
+#[derive(Debug, Decode, Eq, PartialEq, TypeInfo)]
+pub struct TimeoutBuyEvent<T: AtomicSwap> {
+    pub buyer: <T as System>::AccountId,
+    pub hashed_secret: [u8; 32],
+}
+
+
+async fn substrate_client(config: &SubstrateConfig) -> Result<Client::<SubstrateRuntime>, String> {
+    // Events are decoded with the runtime metadata by EventDecoder, the client's own decoder is not used.
+    ClientBuilder::<SubstrateRuntime>::new()
+        .set_url(&config.url)
+        .skip_type_sizes_check()
+        .build().await
+        .map_err(|err| format!("connecting to {} failed: {}", config.url, err))
+}
+
+/// The AtomicSwap events and the structs they are decoded with. Checked against the runtime metadata on startup.
+pub fn atomic_swap_events() -> Vec<(&'static str, MetaType)> {
+    vec![
+        ("AddToOrder", MetaType::new::<AddToOrderEvent<SubstrateRuntime>>()),
+        ("RemoveFromOrder", MetaType::new::<RemoveFromOrderEvent<SubstrateRuntime>>()),
+        ("LockSell", MetaType::new::<LockSellEvent<SubstrateRuntime>>()),
+        ("UnlockSell", MetaType::new::<UnlockSellEvent<SubstrateRuntime>>()),
+        ("TimeoutSell", MetaType::new::<TimeoutSellEvent>()),
+        ("LockBuy", MetaType::new::<LockBuyEvent<SubstrateRuntime>>()),
+        ("UnlockBuy", MetaType::new::<UnlockBuyEvent<SubstrateRuntime>>()),
+        ("TimeoutBuy", MetaType::new::<TimeoutBuyEvent<SubstrateRuntime>>()),
+    ]
+}
+
+pub async fn substrate_audit(db: Arc<dyn OrderStore>, tx: Sender<RequestMessage>, config: &SubstrateConfig, repair: bool) -> Result<(), String> {
+    let api = SubstrateApi {
+        client: substrate_client(config).await?,
+    };
+
+    // Lock state is not audited on Substrate chains: the pallet's lock storage is keyed by hashes of the lock
+    // parameters, which are not part of the metadata the indexer checks, so only order values are compared.
+    let (scope, block_number) = match AuditScope::at_sync_cursor(&*db, &config.name, config.chain_id, config.adapter_id) {
+        Some(scope) => scope,
+        None => {
+            println!("{} audit: nothing indexed yet.", config.name);
+            return Ok(());
+        }
+    };
+    let order_ids = scope.orders.iter().map(|(order_key, _value)| order_key.order_id).collect::<BTreeSet<_>>();
+    let block_hash = api.get_block_hash(block_number).await?;
+    let state = ChainState {
+        order_values: api.get_order_values(&order_ids, block_hash).await?,
+        ..ChainState::default()
+    };
+    report_drift(&*db, &tx, &config.name, &scope, scope.find_drift(&state), repair);
+    Ok(())
+}
+
+/// The order whose value is read from chain state for an event.
+pub fn event_order_id(event: &SwapEvent) -> Option<[u8; 16]> {
+    match *event {
+        SwapEvent::AddToOrder { order_id, .. } |
+        SwapEvent::RemoveFromOrder { order_id, .. } |
+        SwapEvent::LockSell { order_id, .. } |
+        SwapEvent::TimeoutSell { order_id, .. } => Some(order_id),
+        _ => None,
+    }
+}
+
+/// Fill in the order value of an event. Values are read at the end of the block, so every event in a block sees the
+/// value the order has once the whole block has been applied.
+pub fn set_event_order_value(event: &mut SwapEvent, order_values: &BTreeMap<[u8; 16], u128>) {
+    match event {
+        SwapEvent::AddToOrder { order_id, order_value, .. } |
+        SwapEvent::RemoveFromOrder { order_id, order_value, .. } |
+        SwapEvent::LockSell { order_id, order_value, .. } |
+        SwapEvent::TimeoutSell { order_id, order_value, .. } => {
+            *order_value = order_values.get(order_id).cloned().unwrap_or(0);
+        },
+        _ => {},
+    }
+}
+
+/// Decode the data of an AtomicSwap event. Order values are left at 0 until they have been read.
+pub fn decode_swap_event(variant: &str, data: &[u8]) -> Option<SwapEvent> {
+    match variant {
+        "AddToOrder" => {
+            let event = AddToOrderEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("AddToOrderEvent: {:?}", event);
+            let order = OrderStatic {
+                seller: *event.seller.as_ref(),
+                chain_id: event.chain_id,
+                adapter_id: event.adapter_id,
+                asset_id: event.asset_id,
+                price: event.price,
+                foreign_address: event.foreign_address,
+            };
+            let order_id = order.get_order_id();
+            Some(SwapEvent::AddToOrder {
+                order_id: order_id,
+                seller: order.seller,
+                chain_id: order.chain_id,
+                adapter_id: order.adapter_id,
+                asset_id: order.asset_id,
+                price: order.price,
+                foreign_address: order.foreign_address,
+                value: event.value,
+                // Filled in once every event in the block has been decoded.
+                order_value: 0,
+            })
+        },
+        "RemoveFromOrder" => {
+            let event = RemoveFromOrderEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("RemoveFromOrderEvent: {:?}", event);
+            let order = OrderStatic {
+                seller: *event.seller.as_ref(),
+                chain_id :event.chain_id,
+                adapter_id: event.adapter_id,
+                asset_id: event.asset_id,
+                price: event.price,
+                foreign_address: event.foreign_address,
+            };
+            let order_id = order.get_order_id();
+            Some(SwapEvent::RemoveFromOrder {
+                order_id: order_id,
+                value: event.value,
+                order_value: 0,
+            })
+        },
+        "LockSell" => {
+            let event = LockSellEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("LockSellEvent: {:?}", event);
+            Some(SwapEvent::LockSell {
+                order_id: event.order_id,
+                hashed_secret: event.hashed_secret,
+                timeout: event.timeout.into(),
+                value: event.value,
+                order_value: 0,
+            })
+        },
+        "UnlockSell" => {
+            let event = UnlockSellEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("UnlockSellEvent: {:?}", event);
+            Some(SwapEvent::UnlockSell {
+                order_id: event.order_id,
+                hashed_secret: keccak_256(&event.secret),
+                secret: event.secret,
+            })
+        },
+        "TimeoutSell" => {
+            let event = TimeoutSellEvent::decode(&mut &data[..]).unwrap();
+            println!("TimeoutSellEvent: {:?}", event);
+            Some(SwapEvent::TimeoutSell {
+                order_id: event.order_id,
+                hashed_secret: event.hashed_secret,
+                order_value: 0,
+            })
+        },
+        "LockBuy" => {
+            let event = LockBuyEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("LockBuyEvent: {:?}", event);
+            Some(SwapEvent::LockBuy {
+                chain_id: event.chain_id,
+                adapter_id: event.adapter_id,
+                order_id: event.order_id,
+                buyer: *event.buyer.as_ref(),
+                seller: *event.seller.as_ref(),
+                hashed_secret: event.hashed_secret,
+                timeout: event.timeout.into(),
+                value: event.value,
+                foreign_address: event.foreign_address,
+                verified: true,
+            })
+        },
+        "UnlockBuy" => {
+            let event = UnlockBuyEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("UnlockBuyEvent: {:?}", event);
+            Some(SwapEvent::UnlockBuy {
+                buyer: *event.buyer.as_ref(),
+                hashed_secret: event.hashed_secret,
+            })
+        },
+        "TimeoutBuy" => {
+            let event = TimeoutBuyEvent::<SubstrateRuntime>::decode(&mut &data[..]).unwrap();
+            println!("TimeoutBuyEvent: {:?}", event);
+            Some(SwapEvent::TimeoutBuy {
+                buyer: *event.buyer.as_ref(),
+                hashed_secret: event.hashed_secret,
+            })
+        },
+        _ => {
+            println!("variant: {:?}", variant);
+            None
+        },
+    }
+}
+
+/// Complete a decoded event with the chain state read for it.
+pub fn apply_reads(config: &SubstrateConfig, block_number: u64, mut event: SwapEvent, reads: &ChainReads) -> (EventMeta, SwapEvent) {
+    if let (Some(order_id), Some(order_value)) = (event_order_id(&event), reads.order_value) {
+        set_event_order_value(&mut event, &vec![(order_id, order_value)].into_iter().collect());
+    }
+    let meta = EventMeta {
+        chain_id: config.chain_id,
+        adapter_id: config.adapter_id,
+        block_number: block_number,
+        block_time: reads.block_time,
+        tx_hash: reads.extrinsic_hash,
+    };
+    (meta, event)
+}
+
+/// Decode a recorded AtomicSwap event with the chain state recorded with it, without touching the chain.
+pub fn decode_event(config: &SubstrateConfig, block_number: u64, variant: &str, data: &[u8], reads: &ChainReads) -> Option<(EventMeta, SwapEvent)> {
+    decode_swap_event(variant, data).map(|event| apply_reads(config, block_number, event, reads))
+}
+
+/// How to decode the events of a block once the runtime it was executed with is known.
+pub enum RuntimeDecision {
+    /// The block was executed with the runtime the current decoder was built for.
+    Keep,
+    /// The runtime was upgraded, and its AtomicSwap events are decoded with a new decoder from now on.
+    Switch(EventDecoder),
+    /// The runtime was upgraded to an AtomicSwap pallet that does not match the event structs.
+    Pause(String),
+}
+
+/// Decide how to decode a block executed with `block_spec_version`, given the decoder built from that runtime's
+/// metadata. The decoder is only used if the spec version has changed.
+pub fn runtime_decision(spec_version: u32, block_spec_version: u32, block_decoder: Result<EventDecoder, String>) -> RuntimeDecision {
+    if block_spec_version == spec_version {
+        return RuntimeDecision::Keep;
+    }
+    match block_decoder {
+        Ok(decoder) => RuntimeDecision::Switch(decoder),
+        Err(err) => RuntimeDecision::Pause(err),
+    }
+}
+
+/// Stop indexing a chain whose AtomicSwap events can no longer be decoded. The sync cursor stays on the last block
+/// decoded with the old runtime. Indexing does not resume while the indexer is running: once an indexer that decodes
+/// the new events is started, the listener backfills from the sync cursor.
+async fn pause(config: &SubstrateConfig, spec_version: u32, block_number: u64, err: &str) -> ! {
+    loop {
+        eprintln!("!!! {}: INDEXING PAUSED at block {}. Runtime spec version {} has an incompatible AtomicSwap pallet: {}", config.name, block_number, spec_version, err);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+}
+
+/// Follows one Substrate chain, decoding each block with the runtime it was executed with.
+struct SubstrateListener {
+    api: SubstrateApi,
+    db: Arc<dyn OrderStore>,
+    tx: Sender<RequestMessage>,
+    recorder: Option<Arc<Recorder>>,
+    config: SubstrateConfig,
+    spec_version: u32,
+    decoder: EventDecoder,
+}
+
+impl SubstrateListener {
+    /// Switch to the runtime a block was executed with. Events in a block are emitted by the runtime in its parent's
+    /// state, so a runtime upgrade applies from the block after the one that enacted it.
+    async fn check_runtime(&mut self, parent_hash: sp_core::H256, block_number: u64) {
+        let block_spec_version = self.api.get_spec_version(Some(parent_hash)).await;
+        if block_spec_version == self.spec_version { return };
+        let block_decoder = EventDecoder::new(self.api.get_metadata(Some(parent_hash)).await, "AtomicSwap", &atomic_swap_events());
+        match runtime_decision(self.spec_version, block_spec_version, block_decoder) {
+            RuntimeDecision::Keep => {},
+            RuntimeDecision::Switch(decoder) => {
+                println!("{}: runtime upgraded from spec version {} to {} at block {}.", self.config.name, self.spec_version, block_spec_version, block_number);
+                self.decoder = decoder;
+                self.spec_version = block_spec_version;
+            },
+            RuntimeDecision::Pause(err) => pause(&self.config, block_spec_version, block_number, &err).await,
+        }
+    }
+
+    /// Index a block the subscription did not deliver, reading its events from the node.
+    async fn backfill_block(&mut self, block_number: u64) {
+        let block_hash = self.api.get_block_hash(block_number).await.unwrap();
+        let header = self.api.client.header(Some(block_hash)).await.unwrap().unwrap();
+        self.check_runtime(header.parent_hash, block_number).await;
+        let events = self.api.get_events(block_hash).await.unwrap();
+        self.index_block(block_hash, block_number, events.into_iter().collect()).await;
+    }
+
+    /// Index the System.Events of a block. All events in a block are committed together with the sync cursor.
+    async fn index_block(&self, block_hash: sp_core::H256, block_number: u64, events: Vec<StorageData>) {
+        let mut batch = EventBatch::new(&*self.db);
+        let mut pending = Vec::new();
+
+        for data in events {
+            let events = self.decoder.decode_events(&data.0).unwrap();
+
+            for event in events {
+                if event.module != "AtomicSwap" { continue; }
+                let extrinsic_index = event.extrinsic_index;
+
+                let raw_event = RawEvent::Substrate {
+                    module: event.module.clone(),
+                    variant: event.variant.clone(),
+                    data: hex::encode(&event.data),
+                    extrinsic_index: extrinsic_index,
+                };
+                let swap_event = decode_swap_event(&event.variant, &event.data);
+                pending.push((raw_event, extrinsic_index, swap_event));
+            }
+        }
+
+        // Read the block, and the values of every order touched in it at the block itself, once for all its events.
+        let order_ids = pending.iter()
+            .filter_map(|(_, _, swap_event)| swap_event.as_ref().and_then(event_order_id))
+            .collect::<BTreeSet<_>>();
+        let has_events = pending.iter().any(|(_, _, swap_event)| swap_event.is_some());
+        // The state must be read before the block is committed, so a failed read is retried.
+        let block_reads = loop {
+            match self.api.read_block(block_hash, has_events, &order_ids).await {
+                Ok(block_reads) => break block_reads,
+                Err(err) => {
+                    eprintln!("{}: reading block {} failed, retrying: {}", self.config.name, block_number, err);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                },
+            }
+        };
+
+        for (raw_event, extrinsic_index, swap_event) in pending {
+            let reads = match &swap_event {
+                Some(swap_event) => block_reads.event_reads(extrinsic_index, event_order_id(swap_event)),
+                None => ChainReads::default(),
+            };
+            let decoded = swap_event.map(|swap_event| apply_reads(&self.config, block_number, swap_event, &reads));
+            if let Some(recorder) = &self.recorder {
+                recorder.record(&RecordedEvent {
+                    listener: self.config.name.clone(),
+                    block_number: block_number,
+                    raw: raw_event,
+                    reads: reads,
+                });
+            }
+            if let Some((meta, swap_event)) = &decoded {
+                process_event(&mut batch, meta, swap_event);
+            }
+        }
+
+        batch.set_sync_cursor(&self.config.name, block_number);
+        batch.commit(&self.tx);
+    }
+}
+
+pub async fn substrate_listen(db: Arc<dyn OrderStore>, tx: Sender<RequestMessage>, recorder: Option<Arc<Recorder>>, config: SubstrateConfig) {
+    let client = substrate_client(&config).await.unwrap();
+
+    println!("Connected to {}.", config.name);
+
+    let mut versions = client
+        .rpc_client()
+        .subscribe::<RuntimeVersion>("state_subscribeRuntimeVersion", &[], "state_unsubscribeRuntimeVersion")
+        .await.unwrap();
+
+    let api = SubstrateApi {
+        client: client.clone(),
+    };
+    // Indexing carries on from the block after the sync cursor, with the runtime that block was executed with.
+    let cursor = db.get_sync_cursor(&config.name);
+    let runtime_hash = match cursor {
+        Some(block_number) => Some(api.get_block_hash(block_number).await.unwrap()),
+        None => None,
+    };
+    // A pallet that does not match the event structs would silently corrupt every decoded event.
+    let spec_version = api.get_spec_version(runtime_hash).await;
+    let decoder = match EventDecoder::new(api.get_metadata(runtime_hash).await, "AtomicSwap", &atomic_swap_events()) {
+        Ok(decoder) => decoder,
+        Err(err) => panic!("{}: incompatible AtomicSwap pallet: {}", config.name, err),
+    };
+    let mut listener = SubstrateListener {
+        api: api,
+        db: db,
+        tx: tx,
+        recorder: recorder,
+        config: config,
+        spec_version: spec_version,
+        decoder: decoder,
+    };
+
+    // Catch up with the blocks produced since the sync cursor before following new ones.
+    let mut next_block = None;
+    if let Some(block_number) = cursor {
+        let head = listener.api.get_head_number().await.unwrap();
+        for block_number in block_number + 1..=head {
+            listener.backfill_block(block_number).await;
+        }
+        next_block = Some(std::cmp::max(block_number, head) + 1);
+    }
+
+    let mut sub = client.subscribe_events().await.unwrap();
+
+    // Spec version announced by the node for its best block, until the events reach the runtime.
+    let mut upgrade = None;
+
+    loop {
+        // A version change is announced when the block enacting it is imported, before the events of the block after
+        // it, so it is always taken first.
+        let change_set = tokio::select! {
+            biased;
+            version = versions.next() => {
+                match version {
+                    Some(version) => upgrade = match version.spec_version == listener.spec_version {
+                        true => None,
+                        false => Some(version.spec_version),
+                    },
+                    None => break,
+                }
+                continue;
+            },
+            change_set = sub.next() => match change_set {
+                Some(change_set) => change_set,
+                None => break,
+            },
+        };
+        let block_hash = change_set.block;
+        let header = client.header(Some(block_hash)).await.unwrap().unwrap();
+        let block_number: u64 = header.number.into();
+
+        // Blocks produced while catching up, or missed by the subscription, are read from the node. Blocks already
+        // indexed are skipped.
+        if let Some(next_block) = next_block {
+            if block_number < next_block { continue };
+            for block_number in next_block..block_number {
+                listener.backfill_block(block_number).await;
+            }
+        }
+
+        // The runtime is only read while an upgrade is pending.
+        if let Some(announced) = upgrade {
+            listener.check_runtime(header.parent_hash, block_number).await;
+            if listener.spec_version == announced {
+                upgrade = None;
+            }
+        }
+
+        let events = change_set.changes.into_iter().filter_map(|(_key, data)| data).collect();
+        listener.index_block(block_hash, block_number, events).await;
+        next_block = Some(block_number + 1);
+    }
+}
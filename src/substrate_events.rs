@@ -0,0 +1,264 @@
+use codec::{Compact, Decode, Error};
+use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed, StorageEntryType};
+use scale_info::{form::PortableForm, Field, MetaType, PortableRegistry, Registry, TypeDef, TypeDefPrimitive};
+use substrate_subxt::system::Phase;
+
+/// Shape of the SCALE encoding of a type. Wrapper types with a single field have the shape of the field, so an
+/// AccountId32 has the same shape as a [u8; 32].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    Primitive(String),
+    Array(u32, Box<Layout>),
+    Sequence(Box<Layout>),
+    Compact(Box<Layout>),
+    Composite(Vec<Layout>),
+    Variant(Vec<(u8, Vec<Layout>)>),
+    BitSequence,
+}
+
+fn fields_layout(registry: &PortableRegistry, fields: &[Field<PortableForm>]) -> Vec<Layout> {
+    fields.iter().map(|field| layout(registry, field.ty().id())).collect()
+}
+
+/// Layout of a type in a registry.
+pub fn layout(registry: &PortableRegistry, id: u32) -> Layout {
+    let ty = match registry.resolve(id) {
+        Some(ty) => ty,
+        None => return Layout::Primitive(format!("unknown type {}", id)),
+    };
+    match ty.type_def() {
+        TypeDef::Composite(composite) => {
+            let mut fields = fields_layout(registry, composite.fields());
+            match fields.len() {
+                1 => fields.remove(0),
+                _ => Layout::Composite(fields),
+            }
+        },
+        TypeDef::Tuple(tuple) => {
+            let mut fields = tuple.fields().iter().map(|field| layout(registry, field.id())).collect::<Vec<_>>();
+            match fields.len() {
+                1 => fields.remove(0),
+                _ => Layout::Composite(fields),
+            }
+        },
+        TypeDef::Variant(variant) => Layout::Variant(variant.variants().iter()
+            .map(|variant| (variant.index(), fields_layout(registry, variant.fields())))
+            .collect()),
+        TypeDef::Sequence(sequence) => Layout::Sequence(Box::new(layout(registry, sequence.type_param().id()))),
+        TypeDef::Array(array) => Layout::Array(array.len(), Box::new(layout(registry, array.type_param().id()))),
+        TypeDef::Compact(compact) => Layout::Compact(Box::new(layout(registry, compact.type_param().id()))),
+        TypeDef::Primitive(primitive) => Layout::Primitive(format!("{:?}", primitive)),
+        TypeDef::BitSequence(_) => Layout::BitSequence,
+    }
+}
+
+fn skip_bytes(input: &mut &[u8], len: usize) -> Result<(), Error> {
+    if input.len() < len {
+        return Err("Not enough data to skip".into());
+    }
+    *input = &input[len..];
+    Ok(())
+}
+
+fn primitive_size(registry: &PortableRegistry, id: u32) -> Result<usize, Error> {
+    match registry.resolve(id).map(|ty| ty.type_def()) {
+        Some(TypeDef::Primitive(TypeDefPrimitive::U8)) => Ok(1),
+        Some(TypeDef::Primitive(TypeDefPrimitive::U16)) => Ok(2),
+        Some(TypeDef::Primitive(TypeDefPrimitive::U32)) => Ok(4),
+        Some(TypeDef::Primitive(TypeDefPrimitive::U64)) => Ok(8),
+        _ => Err("Unsupported bit sequence store type".into()),
+    }
+}
+
+/// Skip over one value of a type in a registry.
+pub fn skip(registry: &PortableRegistry, id: u32, input: &mut &[u8]) -> Result<(), Error> {
+    let ty = registry.resolve(id).ok_or("Type not found in registry")?;
+    match ty.type_def() {
+        TypeDef::Composite(composite) => {
+            for field in composite.fields() {
+                skip(registry, field.ty().id(), input)?;
+            }
+        },
+        TypeDef::Tuple(tuple) => {
+            for field in tuple.fields() {
+                skip(registry, field.id(), input)?;
+            }
+        },
+        TypeDef::Variant(variant) => {
+            let index = u8::decode(input)?;
+            let variant = variant.variants().iter().find(|variant| variant.index() == index).ok_or("Variant not found")?;
+            for field in variant.fields() {
+                skip(registry, field.ty().id(), input)?;
+            }
+        },
+        TypeDef::Sequence(sequence) => {
+            let len = Compact::<u32>::decode(input)?.0;
+            for _ in 0..len {
+                skip(registry, sequence.type_param().id(), input)?;
+            }
+        },
+        TypeDef::Array(array) => {
+            for _ in 0..array.len() {
+                skip(registry, array.type_param().id(), input)?;
+            }
+        },
+        // Compact encodings of every integer width have the same prefix, so they can be skipped as a u128.
+        TypeDef::Compact(_) => {
+            Compact::<u128>::decode(input)?;
+        },
+        TypeDef::Primitive(primitive) => match primitive {
+            TypeDefPrimitive::Bool | TypeDefPrimitive::U8 | TypeDefPrimitive::I8 => skip_bytes(input, 1)?,
+            TypeDefPrimitive::U16 | TypeDefPrimitive::I16 => skip_bytes(input, 2)?,
+            TypeDefPrimitive::Char | TypeDefPrimitive::U32 | TypeDefPrimitive::I32 => skip_bytes(input, 4)?,
+            TypeDefPrimitive::U64 | TypeDefPrimitive::I64 => skip_bytes(input, 8)?,
+            TypeDefPrimitive::U128 | TypeDefPrimitive::I128 => skip_bytes(input, 16)?,
+            TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => skip_bytes(input, 32)?,
+            TypeDefPrimitive::Str => {
+                let len = Compact::<u32>::decode(input)?.0;
+                skip_bytes(input, len as usize)?;
+            },
+        },
+        TypeDef::BitSequence(bit_sequence) => {
+            let bits = Compact::<u32>::decode(input)?.0 as usize;
+            let store_bits = primitive_size(registry, bit_sequence.bit_store_type().id())? * 8;
+            skip_bytes(input, (bits + store_bits - 1) / store_bits * store_bits / 8)?;
+        },
+    }
+    Ok(())
+}
+
+/// An event from System.Events, with its fields still encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstrateEvent {
+    pub extrinsic_index: Option<u32>,
+    pub module: String,
+    pub variant: String,
+    pub data: Vec<u8>,
+}
+
+/// Splits System.Events into events using the type registry of the runtime metadata, so no type sizes need to be
+/// registered. The events of one pallet are checked against the Rust structs they will be decoded with.
+pub struct EventDecoder {
+    registry: PortableRegistry,
+    events_ty: u32,
+}
+
+impl EventDecoder {
+    /// Check that each variant of a pallet's event enum has the layout of its Rust struct.
+    pub fn from_registry(registry: PortableRegistry, events_ty: u32, pallet_event_ty: u32, expected: &[(&str, MetaType)]) -> Result<EventDecoder, String> {
+        let pallet_event = registry.resolve(pallet_event_ty).ok_or("Pallet event type not found.")?;
+        let variants = match pallet_event.type_def() {
+            TypeDef::Variant(variant) => variant.variants(),
+            _ => return Err("Pallet event type is not an enum.".to_string()),
+        };
+
+        let mut rust_registry = Registry::new();
+        let rust_ids = expected.iter().map(|(_, ty)| rust_registry.register_type(ty).id()).collect::<Vec<_>>();
+        let rust_registry: PortableRegistry = rust_registry.into();
+
+        for ((name, _), rust_id) in expected.iter().zip(rust_ids) {
+            let variant = variants.iter().find(|variant| variant.name().as_str() == *name)
+                .ok_or(format!("Event {} not found in metadata.", name))?;
+            let chain = fields_layout(&registry, variant.fields());
+            let rust = match rust_registry.resolve(rust_id).map(|ty| ty.type_def()) {
+                Some(TypeDef::Composite(composite)) => fields_layout(&rust_registry, composite.fields()),
+                _ => return Err(format!("Event {} is not a struct.", name)),
+            };
+            if chain != rust {
+                return Err(format!("Event {} does not match its struct.\n  metadata: {:?}\n  struct: {:?}", name, chain, rust));
+            }
+        }
+
+        Ok(EventDecoder {
+            registry: registry,
+            events_ty: events_ty,
+        })
+    }
+
+    /// Find System.Events and the pallet's events in V14 runtime metadata.
+    pub fn new(metadata: RuntimeMetadataPrefixed, pallet: &str, expected: &[(&str, MetaType)]) -> Result<EventDecoder, String> {
+        let metadata = match metadata.1 {
+            RuntimeMetadata::V14(metadata) => metadata,
+            _ => return Err("Only V14 metadata is supported.".to_string()),
+        };
+        let events_ty = metadata.pallets.iter()
+            .find(|pallet| pallet.name == "System")
+            .and_then(|pallet| pallet.storage.as_ref())
+            .and_then(|storage| storage.entries.iter().find(|entry| entry.name == "Events"))
+            .and_then(|entry| match &entry.ty {
+                StorageEntryType::Plain(ty) => Some(ty.id()),
+                _ => None,
+            })
+            .ok_or("System.Events not found in metadata.")?;
+        let pallet_event_ty = metadata.pallets.iter()
+            .find(|metadata| metadata.name == pallet)
+            .ok_or(format!("Pallet {} not found in metadata.", pallet))?
+            .event.as_ref()
+            .ok_or(format!("Pallet {} has no events.", pallet))?
+            .ty.id();
+        EventDecoder::from_registry(metadata.types, events_ty, pallet_event_ty, expected)
+    }
+
+    fn decode_record(&self, record_ty: u32, input: &mut &[u8]) -> Result<SubstrateEvent, Error> {
+        let record = self.registry.resolve(record_ty).ok_or("EventRecord not found in registry")?;
+        let fields = match record.type_def() {
+            TypeDef::Composite(composite) => composite.fields(),
+            _ => return Err("EventRecord is not a struct".into()),
+        };
+        let mut event = SubstrateEvent {
+            extrinsic_index: None,
+            module: String::new(),
+            variant: String::new(),
+            data: Vec::new(),
+        };
+        for field in fields {
+            match field.name().map(|name| name.as_str()) {
+                Some("phase") => {
+                    if let Phase::ApplyExtrinsic(index) = Phase::decode(input)? {
+                        event.extrinsic_index = Some(index);
+                    }
+                },
+                Some("event") => {
+                    // The runtime's event enum has a variant for each pallet, wrapping the pallet's event enum.
+                    let outer = match self.registry.resolve(field.ty().id()).map(|ty| ty.type_def()) {
+                        Some(TypeDef::Variant(variant)) => variant,
+                        _ => return Err("Runtime event is not an enum".into()),
+                    };
+                    let index = u8::decode(input)?;
+                    let module = outer.variants().iter().find(|variant| variant.index() == index).ok_or("Pallet not found")?;
+                    let inner_ty = module.fields().get(0).ok_or("Pallet event not found")?.ty().id();
+                    let inner = match self.registry.resolve(inner_ty).map(|ty| ty.type_def()) {
+                        Some(TypeDef::Variant(variant)) => variant,
+                        _ => return Err("Pallet event is not an enum".into()),
+                    };
+                    let index = u8::decode(input)?;
+                    let variant = inner.variants().iter().find(|variant| variant.index() == index).ok_or("Event not found")?;
+                    let start = *input;
+                    for field in variant.fields() {
+                        skip(&self.registry, field.ty().id(), input)?;
+                    }
+                    event.module = module.name().clone();
+                    event.variant = variant.name().clone();
+                    event.data = start[..start.len() - input.len()].to_vec();
+                },
+                _ => skip(&self.registry, field.ty().id(), input)?,
+            }
+        }
+        Ok(event)
+    }
+
+    /// Decode the value of System.Events.
+    pub fn decode_events(&self, data: &[u8]) -> Result<Vec<SubstrateEvent>, Error> {
+        let record_ty = match self.registry.resolve(self.events_ty).map(|ty| ty.type_def()) {
+            Some(TypeDef::Sequence(sequence)) => sequence.type_param().id(),
+            _ => return Err("System.Events is not a sequence".into()),
+        };
+        let input = &mut &data[..];
+        let len = Compact::<u32>::decode(input)?.0;
+        let mut events = Vec::new();
+        for _ in 0..len {
+            events.push(self.decode_record(record_ty, input)?);
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        NoClientAuth, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use crate::config::TlsConfig;
+
+fn load_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let mut reader = BufReader::new(File::open(&config.cert_path).map_err(|err| format!("{}: {}", config.cert_path, err))?);
+    let cert_chain = certs(&mut reader).map_err(|_| format!("{}: invalid certificate", config.cert_path))?;
+    if cert_chain.is_empty() {
+        return Err(format!("{}: no certificates found", config.cert_path));
+    }
+
+    // The key can be PKCS #8 or PKCS #1 (RSA).
+    let key_pem = fs::read(&config.key_path).map_err(|err| format!("{}: {}", config.key_path, err))?;
+    let mut keys = pkcs8_private_keys(&mut &key_pem[..]).map_err(|_| format!("{}: invalid key", config.key_path))?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut &key_pem[..]).map_err(|_| format!("{}: invalid key", config.key_path))?;
+    }
+    let key = keys.into_iter().next().ok_or(format!("{}: no private key found", config.key_path))?;
+
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.set_single_cert(cert_chain, key).map_err(|err| err.to_string())?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn modified(config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(&config.cert_path).and_then(|metadata| metadata.modified()).ok()?;
+    let key = fs::metadata(&config.key_path).and_then(|metadata| metadata.modified()).ok()?;
+    Some((cert, key))
+}
+
+/// TLS for any server socket. The certificate and key are reloaded when their files change, so a renewed
+/// certificate is used for new connections without a restart.
+#[derive(Clone)]
+pub struct Tls {
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl Tls {
+    /// Load the certificate and key, panicking if they are not usable.
+    pub fn new(config: &TlsConfig) -> Tls {
+        let acceptor = match load_acceptor(config) {
+            Ok(acceptor) => acceptor,
+            Err(err) => panic!("TLS: {}", err),
+        };
+        println!("TLS certificate loaded from {}.", config.cert_path);
+        Tls {
+            acceptor: Arc::new(RwLock::new(acceptor)),
+        }
+    }
+
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> io::Result<TlsStream<S>> {
+        let acceptor = self.acceptor.read().unwrap().clone();
+        acceptor.accept(stream).await
+    }
+
+    /// Load the certificate and key again and use them for new connections. On error the previous ones stay in use.
+    pub fn reload(&self, config: &TlsConfig) -> Result<(), String> {
+        *self.acceptor.write().unwrap() = load_acceptor(config)?;
+        Ok(())
+    }
+
+    /// Poll the certificate and key files, reloading them when they change. A certificate that fails to load is
+    /// logged and the previous one stays in use.
+    pub async fn watch(self, config: TlsConfig) {
+        let mut last_modified = modified(&config);
+        loop {
+            tokio::time::sleep(Duration::from_secs(config.reload_interval)).await;
+            let now_modified = modified(&config);
+            if now_modified == last_modified { continue };
+            last_modified = now_modified;
+            match self.reload(&config) {
+                Ok(()) => println!("TLS certificate reloaded from {}.", config.cert_path),
+                Err(err) => println!("TLS: certificate not reloaded: {}", err),
+            }
+        }
+    }
+}
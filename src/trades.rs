@@ -0,0 +1,120 @@
+use crate::shared::*;
+use crate::store::OrderStore;
+
+pub enum TradeSide {
+    Sell,
+    Buy,
+}
+
+/// Record one side of a swap being unlocked. Returns the trade when this unlock completes it.
+///
+/// The listeners are independent, so the sell side can be unlocked before the buy lock on the other chain has been
+/// indexed. The sell leg is then kept until the buy lock arrives.
+pub fn record_unlock(store: &dyn OrderStore, lock_key: &LockKey, side: TradeSide, leg: TradeLeg) -> Option<Trade> {
+    let mut trade = match store.get_trade(lock_key) {
+        Some(trade) => trade,
+        None => {
+            let buy_lock = match store.get_buy_lock(lock_key) {
+                Some(buy_lock) => buy_lock,
+                None => {
+                    println!("trade: buy_lock not found yet: {:?}", hex::encode(lock_key.hashed_secret));
+                    if let TradeSide::Sell = side {
+                        store.put_pending_unlock(lock_key, &leg);
+                    }
+                    return None;
+                }
+            };
+            let order_key = OrderKey {
+                chain_id: lock_key.chain_id,
+                adapter_id: lock_key.adapter_id,
+                order_id: buy_lock.order_id,
+            };
+            let order_static = match store.get_order(&order_key) {
+                Some(order_static) => order_static,
+                None => {
+                    println!("trade: order_static not found: {:?}", hex::encode(buy_lock.order_id));
+                    return None;
+                }
+            };
+            Trade {
+                sell_chain_id: lock_key.chain_id,
+                sell_adapter_id: lock_key.adapter_id,
+                sell_asset_id: <[u8; 8]>::default(),
+                buy_chain_id: order_static.chain_id,
+                buy_adapter_id: order_static.adapter_id,
+                buy_asset_id: order_static.asset_id,
+                order_id: buy_lock.order_id,
+                hashed_secret: lock_key.hashed_secret,
+                price: order_static.price,
+                value: buy_lock.value,
+                buyer: buy_lock.buyer,
+                seller: order_static.seller,
+                sell_unlock: None,
+                buy_unlock: None,
+            }
+        }
+    };
+
+    let unlock = match side {
+        TradeSide::Sell => &mut trade.sell_unlock,
+        TradeSide::Buy => &mut trade.buy_unlock,
+    };
+    // A replayed block unlocks the same side again. The trade has already been counted.
+    if unlock.is_some() {
+        println!("trade: already unlocked: {:?}", hex::encode(lock_key.hashed_secret));
+        return None;
+    }
+    *unlock = Some(leg);
+    store.put_trade(lock_key, &trade);
+
+    if !trade.is_complete() {
+        return None;
+    }
+
+    let trade_list_key = trade.list_key();
+    println!("trade: {:?}", trade_list_key);
+    store.put_trade_list(&trade_list_key);
+    Some(trade)
+}
+
+/// Attach a sell side unlock that was waiting for this buy lock. Returns the trade if that completes it.
+pub fn attach_pending_unlock(store: &dyn OrderStore, lock_key: &LockKey) -> Option<Trade> {
+    let leg = store.get_pending_unlock(lock_key)?;
+    store.delete_pending_unlock(lock_key);
+    record_unlock(store, lock_key, TradeSide::Sell, leg)
+}
+
+/// Completed trades in a market, oldest first, with timestamps between `from` and `to` inclusive.
+pub fn get_trades(store: &dyn OrderStore, sell_chain_id: u32, sell_asset_id: [u8; 8], buy_chain_id: u32, buy_asset_id: [u8; 8], from: u64, to: u64, limit: usize) -> Vec<Trade> {
+    let start_key = TradeListKey {
+        sell_chain_id: sell_chain_id,
+        sell_asset_id: sell_asset_id,
+        buy_chain_id: buy_chain_id,
+        buy_asset_id: buy_asset_id,
+        timestamp: from,
+        sell_adapter_id: u32::default(),
+        hashed_secret: <[u8; 32]>::default(),
+    };
+
+    let iterator = store.iter_from("trade_list", &start_key.serialize());
+    let mut trades: Vec<Trade> = Vec::new();
+
+    for (key, _value) in iterator {
+        if trades.len() >= limit { break };
+        let trade_list_key = TradeListKey::unserialize(key);
+        if trade_list_key.sell_chain_id != sell_chain_id ||
+            trade_list_key.sell_asset_id != sell_asset_id ||
+            trade_list_key.buy_chain_id != buy_chain_id ||
+            trade_list_key.buy_asset_id != buy_asset_id ||
+            trade_list_key.timestamp > to { break };
+
+        let lock_key = LockKey {
+            chain_id: trade_list_key.sell_chain_id,
+            adapter_id: trade_list_key.sell_adapter_id,
+            hashed_secret: trade_list_key.hashed_secret,
+        };
+        trades.push(store.get_trade(&lock_key).unwrap());
+    }
+
+    trades
+}
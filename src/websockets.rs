@@ -1,39 +1,237 @@
 use std::{
+    collections::HashSet,
     net::SocketAddr,
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use rocksdb::{DB, IteratorMode, Direction};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
-use serde::Serialize;
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    protocol::WebSocketConfig,
+    Message,
+};
+use serde::{Serialize, Serializer};
 use web3::futures::{StreamExt, SinkExt};
 use crate::shared::*;
+use crate::store::OrderStore;
+use crate::trades::get_trades;
+use crate::candles::get_candles;
+use crate::assets::{get_asset, find_asset, list_assets, put_asset};
+use crate::config::Config;
+use crate::markets::list_markets;
+use crate::limits::{check_handshake, ConnectionLimiter, RateLimiter};
+use crate::tls::Tls;
+
+/// The schema version used until a connection negotiates another one.
+const DEFAULT_SCHEMA_VERSION: u32 = 1;
+/// From this schema version amounts and timeouts are encoded as decimal strings.
+const STRING_AMOUNTS_SCHEMA_VERSION: u32 = 2;
+/// From this schema version changes to order books are pushed as deltas instead of whole order books.
+const DELTAS_SCHEMA_VERSION: u32 = 3;
+const LATEST_SCHEMA_VERSION: u32 = 3;
+
+/// A u128 amount or timeout. JavaScript numbers lose precision above 2^53, so newer schema versions send a string.
+/// CBOR integers stop at u64::MAX, so CBOR always carries amounts as strings.
+#[derive(Debug)]
+pub enum WireAmount {
+    Number(u128),
+    String(String),
+}
+
+impl WireAmount {
+    pub fn new(amount: u128, schema_version: u32) -> WireAmount {
+        if schema_version >= STRING_AMOUNTS_SCHEMA_VERSION {
+            WireAmount::String(amount.to_string())
+        } else {
+            WireAmount::Number(amount)
+        }
+    }
+}
+
+impl Serialize for WireAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            WireAmount::Number(amount) if serializer.is_human_readable() => serializer.serialize_u128(*amount),
+            WireAmount::Number(amount) => serializer.serialize_str(&amount.to_string()),
+            WireAmount::String(amount) => serializer.serialize_str(amount),
+        }
+    }
+}
+
+/// Ids, addresses, hashes and secrets: hex strings in JSON, byte strings in CBOR.
+#[derive(Debug)]
+pub struct WireBytes(Vec<u8>);
+
+impl WireBytes {
+    pub fn new(bytes: &[u8]) -> WireBytes {
+        WireBytes(bytes.to_vec())
+    }
+}
+
+impl Serialize for WireBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+/// An amount as a decimal string, when its asset is in the registry.
+fn decimal_amount(asset: &Option<Asset>, amount: u128) -> Option<String> {
+    asset.as_ref().map(|asset| format_amount(amount, asset.decimals))
+}
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct JsonOrder {
-    order_id: String,
-    seller: String,
+    order_id: WireBytes,
+    seller: WireBytes,
     chain_id: u32,          // buying chain
     adapter_id: u32,        // buying adapter
-    asset_id: String,      // buying asset
-    price: u128,
-    foreign_address: String,
-    value: u128,
+    asset_id: WireBytes,   // buying asset
+    price: WireAmount,
+    foreign_address: WireBytes,
+    value: WireAmount,
+    // Only present when the assets are in the registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset_symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset_decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_asset_symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_asset_decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_decimal: Option<String>,
+}
+
+impl JsonOrder {
+    fn new(db: &dyn OrderStore, sell_chain_id: u32, sell_adapter_id: u32, order_id: [u8; 16], order_static: OrderStatic, value: u128, schema_version: u32) -> JsonOrder {
+        let buy_asset = get_asset(db, order_static.chain_id, order_static.adapter_id, order_static.asset_id);
+        let sell_asset = get_asset(db, sell_chain_id, sell_adapter_id, <[u8; 8]>::default());
+
+        JsonOrder {
+            order_id: WireBytes::new(&order_id),
+            seller: WireBytes::new(&order_static.seller),
+            chain_id: order_static.chain_id,
+            adapter_id: order_static.adapter_id,
+            asset_id: WireBytes::new(&order_static.asset_id),
+            price: WireAmount::new(order_static.price, schema_version),
+            foreign_address: WireBytes::new(&order_static.foreign_address),
+            value: WireAmount::new(value, schema_version),
+            asset_symbol: buy_asset.as_ref().map(|asset| asset.symbol.clone()),
+            asset_decimals: buy_asset.as_ref().map(|asset| asset.decimals),
+            price_decimal: buy_asset.as_ref().map(|asset| format_amount(order_static.price, asset.decimals)),
+            sell_asset_symbol: sell_asset.as_ref().map(|asset| asset.symbol.clone()),
+            sell_asset_decimals: sell_asset.as_ref().map(|asset| asset.decimals),
+            value_decimal: sell_asset.as_ref().map(|asset| format_amount(value, asset.decimals)),
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct JsonLock {
-    pub buyer: String,
-    pub hashed_secret: String,
-    pub buy_lock_value: u128,
+    pub buyer: WireBytes,
+    pub hashed_secret: WireBytes,
+    pub buy_lock_value: WireAmount,
     pub buy_lock_state: String,
-    pub buy_lock_timeout: u128,
-    pub buy_lock_foreign_address: String,
+    pub buy_lock_timeout: WireAmount,
+    pub buy_lock_foreign_address: WireBytes,
     pub sell_lock_state: String,
-    pub sell_lock_timeout: u128,
-    pub secret: Option<String>,
+    pub sell_lock_timeout: WireAmount,
+    pub secret: Option<WireBytes>,
+    // Only present when the selling asset is in the registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buy_lock_value_decimal: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonTrade {
+    sell_adapter_id: u32,
+    order_id: WireBytes,
+    hashed_secret: WireBytes,
+    buy_adapter_id: u32,
+    price: WireAmount,
+    value: WireAmount,
+    buyer: WireBytes,
+    seller: WireBytes,
+    timestamp: u64,
+    sell_block_time: u64,
+    sell_tx_hash: WireBytes,
+    buy_block_time: u64,
+    buy_tx_hash: WireBytes,
+    // Only present when the assets are in the registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_decimal: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonCandle {
+    start: u64,
+    open: WireAmount,
+    high: WireAmount,
+    low: WireAmount,
+    close: WireAmount,
+    volume: WireAmount,
+    trades: u32,
+    // Only present when the assets are in the registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    high_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    low_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    close_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume_decimal: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonAsset {
+    chain_id: u32,
+    adapter_id: u32,
+    asset_id: WireBytes,
+    symbol: String,
+    decimals: u8,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonMarket {
+    sell_chain_id: u32,
+    sell_asset_id: WireBytes,
+    buy_chain_id: u32,
+    buy_asset_id: WireBytes,
+    order_count: u32,
+    total_value: WireAmount,
+    best_price: Option<WireAmount>,
+    // Only present when the assets are in the registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_asset_symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sell_asset_decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buy_asset_symbol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buy_asset_decimals: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_value_decimal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    best_price_decimal: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -43,75 +241,284 @@ enum JsonResponseMessage {
     #[serde(rename_all = "camelCase")]
     OrderBook {
         sell_chain_id: u32,
-        sell_asset_id: String,
+        sell_asset_id: WireBytes,
         buy_chain_id: u32,
-        buy_asset_id: String,
+        buy_asset_id: WireBytes,
+        // Sequence of the last delta included in the order book.
+        sequence: u64,
         order_book: Vec<JsonOrder>,
     },
     #[serde(rename_all = "camelCase")]
+    OrderBookDelta {
+        sell_chain_id: u32,
+        sell_asset_id: WireBytes,
+        buy_chain_id: u32,
+        buy_asset_id: WireBytes,
+        sequence: u64,
+        change: OrderBookChange,
+        sell_adapter_id: u32,
+        order_id: WireBytes,
+        // Value of the order after the change, 0 when it has been removed.
+        value: WireAmount,
+        // Only present when the order has been added.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        order: Option<JsonOrder>,
+    },
+    #[serde(rename_all = "camelCase")]
     Order {
         sell_chain_id: u32,
         sell_adapter_id: u32,
         order: JsonOrder,
         locks: Vec<JsonLock>,
     },
+    #[serde(rename_all = "camelCase")]
+    Trades {
+        sell_chain_id: u32,
+        sell_asset_id: WireBytes,
+        buy_chain_id: u32,
+        buy_asset_id: WireBytes,
+        trades: Vec<JsonTrade>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Candles {
+        sell_chain_id: u32,
+        sell_asset_id: WireBytes,
+        buy_chain_id: u32,
+        buy_asset_id: WireBytes,
+        interval: CandleInterval,
+        candles: Vec<JsonCandle>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Assets {
+        assets: Vec<JsonAsset>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Markets {
+        markets: Vec<JsonMarket>,
+    },
+    #[serde(rename_all = "camelCase")]
+    SchemaVersion {
+        version: u32,
+    },
+    #[serde(rename_all = "camelCase")]
+    Encoding {
+        encoding: Encoding,
+    },
+    // Published messages were missed; order books must be requested again.
+    #[serde(rename_all = "camelCase")]
+    Resync {
+        missed: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Error {
+        message: String,
+    },
+}
+
+fn error_response_message(message: &str) -> Option<JsonResponseMessage> {
+    Some(JsonResponseMessage::Error {
+        message: message.to_string(),
+    })
+}
+
+/// Text frames are always JSON, whatever the connection's encoding. Binary frames are CBOR, or JSON from clients
+/// that send everything in binary frames.
+pub fn decode_request(msg: &Message) -> Result<RequestMessage, String> {
+    let request = match msg {
+        Message::Text(text) => serde_json::from_str(text).map_err(|err| err.to_string()),
+        Message::Binary(data) => serde_cbor::from_slice(data)
+            .or_else(|err| serde_json::from_slice(data).map_err(|_| err))
+            .map_err(|err| err.to_string()),
+        _ => Err("Unsupported frame.".to_string()),
+    }?;
+    validate_request(&request)?;
+    Ok(request)
+}
+
+fn check_hex(value: &str, len: usize, name: &str) -> Result<(), String> {
+    match hex::decode(value) {
+        Ok(bytes) if bytes.len() == len => Ok(()),
+        _ => Err(format!("{} must be {} bytes in hex.", name, len)),
+    }
+}
+
+/// Check the ids in a request are the hex of ids of the right length, so it can be answered.
+fn validate_request(msg: &RequestMessage) -> Result<(), String> {
+    match msg {
+        RequestMessage::GetOrderBook { sell_asset_id, buy_asset_id, .. } |
+        RequestMessage::GetTrades { sell_asset_id, buy_asset_id, .. } |
+        RequestMessage::GetCandles { sell_asset_id, buy_asset_id, .. } |
+        RequestMessage::SubscribeCandles { sell_asset_id, buy_asset_id, .. } |
+        RequestMessage::UnsubscribeCandles { sell_asset_id, buy_asset_id, .. } => {
+            check_hex(sell_asset_id, 8, "sell_asset_id")?;
+            check_hex(buy_asset_id, 8, "buy_asset_id")
+        },
+        RequestMessage::GetOrder { order_id, .. } => check_hex(order_id, 16, "order_id"),
+        RequestMessage::RegisterAsset { asset_id, .. } => check_hex(asset_id, 8, "asset_id"),
+        _ => Ok(()),
+    }
+}
+
+/// Encode a response in the connection's encoding. An empty frame means there was nothing to respond with.
+fn encode_response(response: Option<JsonResponseMessage>, encoding: Encoding) -> Message {
+    match (encoding, response) {
+        (Encoding::Json, Some(response)) => Message::Text(serde_json::to_string(&response).unwrap()),
+        (Encoding::Json, None) => Message::Text("".to_string()),
+        (Encoding::Cbor, Some(response)) => Message::Binary(serde_cbor::to_vec(&response).unwrap()),
+        (Encoding::Cbor, None) => Message::Binary(Vec::new()),
+    }
+}
+
+fn error_response(status: StatusCode, message: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message));
+    *response.status_mut() = status;
+    response
+}
+
+/// API key from the X-Api-Key header, or the api_key query parameter for browsers, which cannot set headers.
+fn request_api_key(request: &Request) -> Option<String> {
+    if let Some(api_key) = request.headers().get("X-Api-Key").and_then(|value| value.to_str().ok()) {
+        return Some(api_key.to_string());
+    }
+    request.uri().query()?.split('&')
+        .find_map(|param| param.strip_prefix("api_key="))
+        .map(|api_key| api_key.to_string())
+}
+
+type CandleSubscription = (u32, String, u32, String, CandleInterval);
+
+fn candle_subscription(msg: &RequestMessage) -> Option<CandleSubscription> {
+    match msg {
+        RequestMessage::GetCandles { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval, .. } |
+        RequestMessage::SubscribeCandles { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval } |
+        RequestMessage::UnsubscribeCandles { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval } => {
+            Some((*sell_chain_id, sell_asset_id.to_lowercase(), *buy_chain_id, buy_asset_id.to_lowercase(), *interval))
+        },
+        _ => None,
+    }
+}
+
+fn candles_response(db: &dyn OrderStore, sell_chain_id: u32, sell_asset_id: String, buy_chain_id: u32, buy_asset_id: String, interval: CandleInterval, from: u64, to: u64, limit: u32, schema_version: u32) -> JsonResponseMessage {
+    let sell_asset_id = vector_as_u8_8_array(&hex::decode(sell_asset_id).unwrap());
+    let buy_asset_id = vector_as_u8_8_array(&hex::decode(buy_asset_id).unwrap());
+    // Prices are in the buying asset, volume in the selling asset.
+    let sell_asset = find_asset(db, sell_chain_id, sell_asset_id);
+    let buy_asset = find_asset(db, buy_chain_id, buy_asset_id);
+    let candles = get_candles(db,
+        sell_chain_id,
+        sell_asset_id,
+        buy_chain_id,
+        buy_asset_id,
+        interval,
+        from,
+        to,
+        limit as usize,
+    );
+
+    JsonResponseMessage::Candles {
+        sell_chain_id: sell_chain_id,
+        sell_asset_id: WireBytes::new(&sell_asset_id),
+        buy_chain_id: buy_chain_id,
+        buy_asset_id: WireBytes::new(&buy_asset_id),
+        interval: interval,
+        candles: candles.into_iter().map(|(start, candle)| JsonCandle {
+            start: start,
+            open: WireAmount::new(candle.open, schema_version),
+            high: WireAmount::new(candle.high, schema_version),
+            low: WireAmount::new(candle.low, schema_version),
+            close: WireAmount::new(candle.close, schema_version),
+            volume: WireAmount::new(candle.volume, schema_version),
+            trades: candle.trades,
+            open_decimal: decimal_amount(&buy_asset, candle.open),
+            high_decimal: decimal_amount(&buy_asset, candle.high),
+            low_decimal: decimal_amount(&buy_asset, candle.low),
+            close_decimal: decimal_amount(&buy_asset, candle.close),
+            volume_decimal: decimal_amount(&sell_asset, candle.volume),
+        }).collect(),
+    }
+}
+
+fn assets_response(db: &dyn OrderStore) -> JsonResponseMessage {
+    JsonResponseMessage::Assets {
+        assets: list_assets(db).into_iter().map(|(asset_key, asset)| JsonAsset {
+            chain_id: asset_key.chain_id,
+            adapter_id: asset_key.adapter_id,
+            asset_id: WireBytes::new(&asset_key.asset_id),
+            symbol: asset.symbol,
+            decimals: asset.decimals,
+        }).collect(),
+    }
 }
 
-async fn process_msg(db: &Arc<DB>, msg: RequestMessage) -> String {
+/// The response to a request, or None if there is nothing to respond with.
+async fn process_request(db: &dyn OrderStore, msg: RequestMessage, schema_version: u32, encoding: Encoding) -> Option<JsonResponseMessage> {
     println!("msg: {:?}", msg);
 
     match msg {
         RequestMessage::GetOrderBook { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id } => {
             println!("getOrderBook");
-            let start_key = OrderListKey {
+            let market_key = MarketKey {
                 sell_chain_id: sell_chain_id,
                 sell_asset_id: vector_as_u8_8_array(&hex::decode(sell_asset_id.clone()).unwrap()),
                 buy_chain_id: buy_chain_id,
                 buy_asset_id: vector_as_u8_8_array(&hex::decode(buy_asset_id.clone()).unwrap()),
-                value: u128::default(),
-                sell_adapter_id: u32::default(),
-                order_id: <[u8; 16]>::default(),
             };
 
-            let iterator = db.iterator_cf(&db.cf_handle("order_list").unwrap(), IteratorMode::From(&start_key.serialize(), Direction::Forward));
-            let order_list_keys = iterator.collect::<Vec<_>>();
-            let mut orderbook: Vec<JsonOrder> = Vec::new();
-            for order_list_key in order_list_keys {
-                println!("{:?}", order_list_key);
-                let order_list_key = OrderListKey::unserialize(order_list_key.0.to_vec());
-                if order_list_key.sell_chain_id != sell_chain_id { break };
-
-                println!("{:?}", order_list_key);
+            // A delta committed while the order book is being read would leave it between two sequence numbers.
+            let (sequence, orderbook) = loop {
+                let sequence = db.get_market_sequence(&market_key);
+                let mut orderbook: Vec<JsonOrder> = Vec::new();
+                for order_list_key in db.iter_market(&market_key) {
+                    println!("{:?}", order_list_key);
+
+                    let order_key = OrderKey {
+                        chain_id: order_list_key.sell_chain_id,
+                        adapter_id: order_list_key.sell_adapter_id,
+                        order_id: order_list_key.order_id,
+                    };
 
-                let order_key = OrderKey {
-                    chain_id: order_list_key.sell_chain_id,
-                    adapter_id: order_list_key.sell_adapter_id,
-                    order_id: order_list_key.order_id,
-                };
+                    let order_static = db.get_order(&order_key).unwrap();
+                    println!("{:?}", order_static);
 
-                let order_static: OrderStatic = bincode::deserialize(&db.get_cf(&db.cf_handle("order_static").unwrap(), order_key.serialize()).unwrap().unwrap()).unwrap();
-                println!("{:?}", order_static);
-
-                orderbook.push(JsonOrder {
-                    order_id: hex::encode(order_list_key.order_id),
-                    seller: hex::encode(order_static.seller),
-                    chain_id: order_static.chain_id,
-                    adapter_id: order_static.adapter_id,
-                    asset_id: hex::encode(order_static.asset_id),
-                    price: order_static.price,
-                    foreign_address: hex::encode(order_static.foreign_address),
-                    value: order_list_key.value,
-                });
-            }
+                    orderbook.push(JsonOrder::new(db, sell_chain_id, order_list_key.sell_adapter_id, order_list_key.order_id, order_static, order_list_key.value, schema_version));
+                }
+                if db.get_market_sequence(&market_key) == sequence {
+                    break (sequence, orderbook);
+                }
+            };
 
             let response = JsonResponseMessage::OrderBook {
                 sell_chain_id: sell_chain_id,
-                sell_asset_id: sell_asset_id,
+                sell_asset_id: WireBytes::new(&market_key.sell_asset_id),
                 buy_chain_id: buy_chain_id,
-                buy_asset_id: buy_asset_id,
+                buy_asset_id: WireBytes::new(&market_key.buy_asset_id),
+                sequence: sequence,
                 order_book: orderbook,
             };
-            serde_json::to_string(&response).unwrap()
+            Some(response)
+        },
+        RequestMessage::OrderBookDelta { market, sequence, change, sell_adapter_id, order_id, value } => {
+            let order = match change {
+                OrderBookChange::Added => db.get_order(&OrderKey {
+                    chain_id: market.sell_chain_id,
+                    adapter_id: sell_adapter_id,
+                    order_id: order_id,
+                }).map(|order_static| JsonOrder::new(db, market.sell_chain_id, sell_adapter_id, order_id, order_static, value, schema_version)),
+                _ => None,
+            };
+            let response = JsonResponseMessage::OrderBookDelta {
+                sell_chain_id: market.sell_chain_id,
+                sell_asset_id: WireBytes::new(&market.sell_asset_id),
+                buy_chain_id: market.buy_chain_id,
+                buy_asset_id: WireBytes::new(&market.buy_asset_id),
+                sequence: sequence,
+                change: change,
+                sell_adapter_id: sell_adapter_id,
+                order_id: WireBytes::new(&order_id),
+                value: WireAmount::new(value, schema_version),
+                order: order,
+            };
+            Some(response)
         },
         RequestMessage::GetOrder { sell_chain_id, sell_adapter_id, order_id } => {
             println!("getOrder");
@@ -122,42 +529,22 @@ async fn process_msg(db: &Arc<DB>, msg: RequestMessage) -> String {
                 adapter_id: sell_adapter_id,
                 order_id: order_id,
             };
-            let option = db.get_cf(&db.cf_handle("order_value").unwrap(), order_key.serialize()).unwrap();
+            let option = db.get_order_value(&order_key);
             println!("order_value: {:?}", option);
 
             match option {
-                Some(result) => {
-                    let value = u128::from_be_bytes(vector_as_u8_16_array(&result));
+                Some(value) => {
                     println!("value: {:?}", value);
 
-                    let order_static: OrderStatic = bincode::deserialize(&db.get_cf(&db.cf_handle("order_static").unwrap(), order_key.serialize()).unwrap().unwrap()).unwrap();
+                    let order_static = db.get_order(&order_key).unwrap();
                     println!("{:?}", order_static);
 
-                    let order = JsonOrder {
-                        order_id: hex::encode(order_id),
-                        seller: hex::encode(order_static.seller),
-                        chain_id: order_static.chain_id,
-                        adapter_id: order_static.adapter_id,
-                        asset_id: hex::encode(order_static.asset_id),
-                        price: order_static.price,
-                        foreign_address: hex::encode(order_static.foreign_address),
-                        value: value,
-                    };
+                    let order = JsonOrder::new(db, sell_chain_id, sell_adapter_id, order_id, order_static, value, schema_version);
 
-                    let order_lock_list_key = OrderLockListKey {
-                        chain_id: sell_chain_id,
-                        adapter_id: sell_adapter_id,
-                        order_id: order_id,
-                        value: u128::default(),
-                        hashed_secret: <[u8; 32]>::default(),
-                    };
-
-                    let iterator = db.iterator_cf(&db.cf_handle("order_lock_list").unwrap(), IteratorMode::From(&order_lock_list_key.serialize(), Direction::Forward));
+                    let sell_asset = get_asset(db, sell_chain_id, sell_adapter_id, <[u8; 8]>::default());
                     let mut locks: Vec<JsonLock> = Vec::new();
 
-                    for (key, _value) in iterator {
-                        let order_lock_list_key = OrderLockListKey::unserialize(key.to_vec());
-                        if order_lock_list_key.order_id != order_id { break };
+                    for order_lock_list_key in db.iter_order_locks(&order_key) {
                         println!("hashed_secret: {:?}", order_lock_list_key.hashed_secret);
 
                         let lock_key = LockKey {
@@ -166,12 +553,11 @@ async fn process_msg(db: &Arc<DB>, msg: RequestMessage) -> String {
                             hashed_secret: order_lock_list_key.hashed_secret,
                         };
 
-                        let result = db.get_cf(&db.cf_handle("buy_lock").unwrap(), lock_key.serialize()).unwrap().unwrap();
-                        let buy_lock: BuyLock = bincode::deserialize(&result).unwrap();
+                        let buy_lock = db.get_buy_lock(&lock_key).unwrap();
                         println!("buy_lock: {:?}", buy_lock);
 
-                        let sell_lock: SellLock = match db.get_cf(&db.cf_handle("sell_lock").unwrap(), lock_key.serialize()).unwrap() {
-                            Some(result) => bincode::deserialize(&result).unwrap(),
+                        let sell_lock = match db.get_sell_lock(&lock_key) {
+                            Some(sell_lock) => sell_lock,
                             None => SellLock {
                                 timeout: 0,
                                 state: LockState::NotLocked,
@@ -182,18 +568,19 @@ async fn process_msg(db: &Arc<DB>, msg: RequestMessage) -> String {
                         println!("sell_lock: {:?}", sell_lock);
 
                         locks.push(JsonLock{
-                            buyer: hex::encode(buy_lock.buyer),
-                            hashed_secret: hex::encode(order_lock_list_key.hashed_secret),
-                            buy_lock_value: buy_lock.value,
+                            buyer: WireBytes::new(&buy_lock.buyer),
+                            hashed_secret: WireBytes::new(&order_lock_list_key.hashed_secret),
+                            buy_lock_value: WireAmount::new(buy_lock.value, schema_version),
                             buy_lock_state: buy_lock.state.to_string(),
-                            buy_lock_timeout: buy_lock.timeout,
-                            buy_lock_foreign_address: hex::encode(buy_lock.foreign_address),
+                            buy_lock_timeout: WireAmount::new(buy_lock.timeout, schema_version),
+                            buy_lock_foreign_address: WireBytes::new(&buy_lock.foreign_address),
                             sell_lock_state: sell_lock.state.to_string(),
-                            sell_lock_timeout: sell_lock.timeout,
+                            sell_lock_timeout: WireAmount::new(sell_lock.timeout, schema_version),
                             secret: match sell_lock.secret {
-                                Some(secret) => Some(hex::encode(secret)),
+                                Some(secret) => Some(WireBytes::new(&secret)),
                                 None => None,
                             },
+                            buy_lock_value_decimal: decimal_amount(&sell_asset, buy_lock.value),
                         });
                     }
 
@@ -203,61 +590,283 @@ async fn process_msg(db: &Arc<DB>, msg: RequestMessage) -> String {
                         order: order,
                         locks: locks,
                     };
-                    serde_json::to_string(&response).unwrap()
+                    Some(response)
                 },
-                None => "".to_string(),
+                None => None,
+            }
+        },
+        RequestMessage::GetTrades { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, from, to, limit } => {
+            println!("getTrades");
+
+            let sell_asset_id = vector_as_u8_8_array(&hex::decode(sell_asset_id).unwrap());
+            let buy_asset_id = vector_as_u8_8_array(&hex::decode(buy_asset_id).unwrap());
+            let trades = get_trades(db,
+                sell_chain_id,
+                sell_asset_id,
+                buy_chain_id,
+                buy_asset_id,
+                from.unwrap_or(0),
+                to.unwrap_or(u64::MAX),
+                limit.unwrap_or(100) as usize,
+            );
+
+            let mut json_trades: Vec<JsonTrade> = Vec::new();
+            for trade in trades {
+                let sell_unlock = trade.sell_unlock.clone().unwrap();
+                let buy_unlock = trade.buy_unlock.clone().unwrap();
+                let sell_asset = get_asset(db, trade.sell_chain_id, trade.sell_adapter_id, trade.sell_asset_id);
+                let buy_asset = get_asset(db, trade.buy_chain_id, trade.buy_adapter_id, trade.buy_asset_id);
+                json_trades.push(JsonTrade {
+                    sell_adapter_id: trade.sell_adapter_id,
+                    order_id: WireBytes::new(&trade.order_id),
+                    hashed_secret: WireBytes::new(&trade.hashed_secret),
+                    buy_adapter_id: trade.buy_adapter_id,
+                    price: WireAmount::new(trade.price, schema_version),
+                    value: WireAmount::new(trade.value, schema_version),
+                    buyer: WireBytes::new(&trade.buyer),
+                    seller: WireBytes::new(&trade.seller),
+                    timestamp: trade.timestamp(),
+                    sell_block_time: sell_unlock.block_time,
+                    sell_tx_hash: WireBytes::new(&sell_unlock.tx_hash),
+                    buy_block_time: buy_unlock.block_time,
+                    buy_tx_hash: WireBytes::new(&buy_unlock.tx_hash),
+                    price_decimal: decimal_amount(&buy_asset, trade.price),
+                    value_decimal: decimal_amount(&sell_asset, trade.value),
+                });
             }
+
+            let response = JsonResponseMessage::Trades {
+                sell_chain_id: sell_chain_id,
+                sell_asset_id: WireBytes::new(&sell_asset_id),
+                buy_chain_id: buy_chain_id,
+                buy_asset_id: WireBytes::new(&buy_asset_id),
+                trades: json_trades,
+            };
+            Some(response)
+        },
+        RequestMessage::GetCandles { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval, from, to, limit } => {
+            println!("getCandles");
+            Some(candles_response(db, sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval, from.unwrap_or(0), to.unwrap_or(u64::MAX), limit.unwrap_or(500), schema_version))
+        },
+        RequestMessage::SubscribeCandles { sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval } => {
+            println!("subscribeCandles");
+            // Reply with the current candle so the client has a starting point.
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+            Some(candles_response(db, sell_chain_id, sell_asset_id, buy_chain_id, buy_asset_id, interval, interval.start(now), u64::MAX, 1, schema_version))
+        },
+        RequestMessage::UnsubscribeCandles { .. } => {
+            println!("unsubscribeCandles");
+            None
+        },
+        RequestMessage::ListAssets => {
+            println!("listAssets");
+            Some(assets_response(db))
+        },
+        RequestMessage::ListMarkets => {
+            println!("listMarkets");
+            let response = JsonResponseMessage::Markets {
+                markets: list_markets(db).into_iter().map(|(market_key, market, best_price)| {
+                    let sell_asset = find_asset(db, market_key.sell_chain_id, market_key.sell_asset_id);
+                    let buy_asset = find_asset(db, market_key.buy_chain_id, market_key.buy_asset_id);
+                    JsonMarket {
+                        sell_chain_id: market_key.sell_chain_id,
+                        sell_asset_id: WireBytes::new(&market_key.sell_asset_id),
+                        buy_chain_id: market_key.buy_chain_id,
+                        buy_asset_id: WireBytes::new(&market_key.buy_asset_id),
+                        order_count: market.order_count,
+                        total_value: WireAmount::new(market.total_value, schema_version),
+                        best_price: best_price.map(|best_price| WireAmount::new(best_price, schema_version)),
+                        sell_asset_symbol: sell_asset.as_ref().map(|asset| asset.symbol.clone()),
+                        sell_asset_decimals: sell_asset.as_ref().map(|asset| asset.decimals),
+                        buy_asset_symbol: buy_asset.as_ref().map(|asset| asset.symbol.clone()),
+                        buy_asset_decimals: buy_asset.as_ref().map(|asset| asset.decimals),
+                        total_value_decimal: decimal_amount(&sell_asset, market.total_value),
+                        best_price_decimal: best_price.and_then(|best_price| decimal_amount(&buy_asset, best_price)),
+                    }
+                }).collect(),
+            };
+            Some(response)
+        },
+        RequestMessage::RegisterAsset { chain_id, adapter_id, asset_id, symbol, decimals } => {
+            println!("registerAsset");
+            let asset_key = AssetKey {
+                chain_id: chain_id,
+                adapter_id: adapter_id,
+                asset_id: vector_as_u8_8_array(&hex::decode(asset_id).unwrap()),
+            };
+            put_asset(db, &asset_key, &Asset {
+                symbol: symbol,
+                decimals: decimals,
+            });
+            Some(assets_response(db))
+        },
+        RequestMessage::SetSchemaVersion { .. } => {
+            println!("setSchemaVersion");
+            let response = JsonResponseMessage::SchemaVersion {
+                version: schema_version,
+            };
+            Some(response)
+        },
+        RequestMessage::SetEncoding { .. } => {
+            println!("setEncoding");
+            Some(JsonResponseMessage::Encoding {
+                encoding: encoding,
+            })
+        },
+    }
+}
+
+/// The message to push to a connection for a published one. Clients on the default schema version predate deltas,
+/// so they are still sent the whole order book.
+pub fn push_message(msg: RequestMessage, schema_version: u32) -> RequestMessage {
+    match msg {
+        RequestMessage::OrderBookDelta { market, .. } if schema_version < DELTAS_SCHEMA_VERSION => RequestMessage::GetOrderBook {
+            sell_chain_id: market.sell_chain_id,
+            sell_asset_id: hex::encode(market.sell_asset_id),
+            buy_chain_id: market.buy_chain_id,
+            buy_asset_id: hex::encode(market.buy_asset_id),
         },
+        msg => msg,
     }
 }
 
-async fn handle_connection(raw_stream: TcpStream, addr: SocketAddr, db: Arc<DB>, mut rx: broadcast::Receiver<RequestMessage>) {
-    println!("Incoming TCP connection from: {}", addr);
+pub async fn process_msg(db: &dyn OrderStore, msg: RequestMessage, schema_version: u32, encoding: Encoding) -> Message {
+    encode_response(process_request(db, msg, schema_version, encoding).await, encoding)
+}
 
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(raw_stream: S, addr: SocketAddr, db: Arc<dyn OrderStore>, mut rx: broadcast::Receiver<RequestMessage>, config: Arc<Config>, limiter: Arc<ConnectionLimiter>) {
+    let mut ws_config = WebSocketConfig::default();
+    ws_config.max_frame_size = Some(config.websocket.max_frame_size);
+    ws_config.max_message_size = Some(config.websocket.max_frame_size);
+
+    // Set by the handshake callback once the client is admitted. The guard is released when the connection closes.
+    let mut admitted = None;
+    let callback = |request: &Request, response: Response| {
+        let origin = request.headers().get("Origin").and_then(|value| value.to_str().ok());
+        let quota = match check_handshake(&config.websocket, origin, request_api_key(request).as_deref()) {
+            Ok(quota) => quota,
+            Err(err) => return Err(error_response(StatusCode::FORBIDDEN, err)),
+        };
+        let guard = match limiter.acquire(addr.ip(), quota.max_connections_per_ip) {
+            Some(guard) => guard,
+            None => return Err(error_response(StatusCode::TOO_MANY_REQUESTS, "Too many connections.".to_string())),
+        };
+        admitted = Some((quota, guard));
+        Ok(response)
+    };
+    let ws_stream = match tokio_tungstenite::accept_hdr_async_with_config(raw_stream, callback, Some(ws_config)).await {
+        Ok(ws_stream) => ws_stream,
+        Err(err) => {
+            println!("WebSocket handshake failed: {}: {}", addr, err);
+            return;
+        }
+    };
+    let (quota, _guard) = admitted.unwrap();
+    let mut rate_limiter = RateLimiter::new(&quota);
     println!("WebSocket connection established: {}", addr);
 
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    let mut candle_subscriptions: HashSet<CandleSubscription> = HashSet::new();
+    let mut schema_version = DEFAULT_SCHEMA_VERSION;
+    let mut encoding = Encoding::Json;
 
-
-/*
-    let mut iterator = db.iterator_cf(&db.cf_handle("order_value").unwrap(), IteratorMode::Start);
-    let orders = iterator.collect::<Vec<_>>();
-    println!("Orders: {:?}", orders);
-    let mut iterator = db.iterator_cf(&db.cf_handle("order_static").unwrap(), IteratorMode::Start);
-    let orders = iterator.collect::<Vec<_>>();
-    println!("Orders: {:?}", orders);
-*/
+    // A send fails once the client has gone, which ends the connection like a close.
     loop {
         tokio::select! {
             Some(msg) = ws_receiver.next() => {
-                let msg = msg.unwrap();
+                // Includes frames over the size limit.
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        println!("WebSocket error: {}: {}", addr, err);
+                        break;
+                    }
+                };
                 if msg.is_text() || msg.is_binary() {
-                    let json = process_msg(&db, serde_json::from_str(msg.to_text().unwrap()).unwrap()).await;
-                    ws_sender.send(tokio_tungstenite::tungstenite::Message::Text(json)).await.unwrap();
+                    if !rate_limiter.check() {
+                        if ws_sender.send(encode_response(error_response_message("Rate limit exceeded."), encoding)).await.is_err() { break };
+                        continue;
+                    }
+                    let msg = match decode_request(&msg) {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            if ws_sender.send(encode_response(error_response_message(&err), encoding)).await.is_err() { break };
+                            continue;
+                        }
+                    };
+                    match msg {
+                        RequestMessage::SubscribeCandles { .. } => { candle_subscriptions.insert(candle_subscription(&msg).unwrap()); },
+                        RequestMessage::UnsubscribeCandles { .. } => {
+                            candle_subscriptions.remove(&candle_subscription(&msg).unwrap());
+                            continue;
+                        },
+                        RequestMessage::SetSchemaVersion { version } => {
+                            // Unsupported versions leave the current one in place; the reply tells the client which is in use.
+                            if version >= DEFAULT_SCHEMA_VERSION && version <= LATEST_SCHEMA_VERSION {
+                                schema_version = version;
+                            }
+                        },
+                        // The reply is the first message in the new encoding.
+                        RequestMessage::SetEncoding { encoding: new_encoding } => encoding = new_encoding,
+                        RequestMessage::RegisterAsset { .. } => {
+                            if !config.allow_asset_registration {
+                                println!("Asset registration not allowed: {}", addr);
+                                if ws_sender.send(encode_response(error_response_message("Asset registration not allowed."), encoding)).await.is_err() { break };
+                                continue;
+                            }
+                        },
+                        _ => {},
+                    }
+                    if ws_sender.send(process_msg(&*db, msg, schema_version, encoding).await).await.is_err() { break };
                 }
             }
             msg = rx.recv() => {
-                let json = process_msg(&db, msg.unwrap()).await;
-                ws_sender.send(tokio_tungstenite::tungstenite::Message::Text(json)).await.unwrap();
+                let msg = match msg {
+                    Ok(msg) => push_message(msg, schema_version),
+                    // Deltas were dropped, so the client's order books can no longer be kept up to date.
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        println!("WebSocket client lagging, {} messages missed: {}", missed, addr);
+                        if ws_sender.send(encode_response(Some(JsonResponseMessage::Resync { missed: missed }), encoding)).await.is_err() { break };
+                        continue;
+                    },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                // Candle updates only go to connections subscribed to them.
+                if let RequestMessage::GetCandles { .. } = msg {
+                    if !candle_subscriptions.contains(&candle_subscription(&msg).unwrap()) { continue };
+                }
+                if ws_sender.send(process_msg(&*db, msg, schema_version, encoding).await).await.is_err() { break };
             }
         }
     }
 }
 
-
-pub async fn websockets_listen(db: Arc<DB>, tx: broadcast::Sender<RequestMessage>) {
-    let addr = "127.0.0.1:8080".to_string();
+pub async fn websockets_listen(db: Arc<dyn OrderStore>, tx: broadcast::Sender<RequestMessage>, config: Arc<Config>, tls: Option<Tls>) {
+    let addr = config.websocket.address.clone();
 
     // Create the event loop and TCP listener we'll accept connections on.
     let try_socket = TcpListener::bind(&addr).await;
     let listener = try_socket.expect("Failed to bind");
-    println!("Listening on: {}", addr);
+    println!("Listening on: {}{}", addr, if tls.is_some() { " (TLS)" } else { "" });
+
+    let limiter = Arc::new(ConnectionLimiter::default());
 
     // Let's spawn the handling of each connection in a separate task.
     while let Ok((stream, addr)) = listener.accept().await {
-        tokio::spawn(handle_connection(stream, addr, db.clone(), tx.subscribe()));
+        println!("Incoming TCP connection from: {}", addr);
+        let db = db.clone();
+        let rx = tx.subscribe();
+        let config = config.clone();
+        let limiter = limiter.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => handle_connection(stream, addr, db, rx, config, limiter).await,
+                    Err(err) => println!("TLS handshake failed: {}: {}", addr, err),
+                },
+                None => handle_connection(stream, addr, db, rx, config, limiter).await,
+            }
+        });
     }
 }